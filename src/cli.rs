@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use log::LevelFilter;
+
+/// A language server for diffs.  Hovers, definitions and references on a line
+/// of a diff are answered by the language server of the file it came from.
+#[derive(Debug, Parser)]
+#[command(name = "diff-lsp", version, about)]
+pub struct Cli {
+    /// The project root paths in diffs are relative to.  The editor's
+    /// initializationOptions or rootUri are used when not given.
+    #[arg(long)]
+    pub root: Option<String>,
+
+    /// A subfolder of the root to run backends in
+    #[arg(long)]
+    pub worktree: Option<String>,
+
+    /// Languages to start backends for straight away, e.g. --lang go,rust.
+    /// Others start when a diff with their files is opened.
+    #[arg(long = "lang", value_delimiter = ',')]
+    pub languages: Vec<String>,
+
+    /// Config file to use instead of ~/.config/diff-lsp/config.toml
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Where to write the log
+    #[arg(long, default_value = "~/.diff-lsp.log")]
+    pub log_file: String,
+
+    /// One of off, error, warn, info, debug or trace
+    #[arg(long, default_value = "info", value_parser = parse_level)]
+    pub log_level: LevelFilter,
+
+    /// Read the root, worktree and languages from an initialization tempfile,
+    /// as older clients write them.  Other flags override what it says.
+    #[arg(long)]
+    pub init_file: Option<PathBuf>,
+
+    /// Don't fetch from the remote when starting or refreshing
+    #[arg(long)]
+    pub no_fetch: bool,
+
+    /// Talk LSP over stdin and stdout.  This is the default.
+    #[arg(long, conflicts_with = "listen")]
+    pub stdio: bool,
+
+    /// Talk LSP over a TCP connection accepted on this address, e.g.
+    /// 127.0.0.1:9257
+    #[arg(long)]
+    pub listen: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    level
+        .parse()
+        .map_err(|_| format!("unknown log level {:?}", level))
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print which backends can be found, and their versions
+    Doctor,
+    /// Print a diff as the editor should show it, turning saved GitHub,
+    /// Gerrit and GitLab reviews into text
+    Render { file: PathBuf },
+    /// Serve many editor sessions, keeping backends running between them
+    Daemon {
+        #[command(flatten)]
+        address: AddressArgs,
+    },
+    /// Connect stdin and stdout to a daemon, starting one if none is running
+    Proxy {
+        #[command(flatten)]
+        address: AddressArgs,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct AddressArgs {
+    /// Unix socket to listen or connect on, $XDG_RUNTIME_DIR/diff-lsp.sock by
+    /// default
+    #[arg(long)]
+    pub socket: Option<PathBuf>,
+
+    /// TCP address to listen or connect on instead of a Unix socket
+    #[arg(long, conflicts_with = "socket")]
+    pub tcp: Option<String>,
+}
@@ -1,8 +1,12 @@
 use anyhow::{anyhow, Result};
 use expanduser::expanduser;
 use log::info;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::thread;
 
 use tower_lsp::lsp_types::request::{GotoTypeDefinitionParams, GotoTypeDefinitionResponse};
 
@@ -10,30 +14,141 @@ use std::{
     fs::canonicalize,
     //thread::{spawn},
     //path::{PathBuf}, io::Read,
-    process::{Child, Command, Stdio},
+    process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, Stdio},
 };
 use tower_lsp::lsp_types::*;
 
 use serde::Serialize;
+
+use crate::config::BackendConfig;
 use serde_json::{json, Value};
 
 const HEADER_CONTENT_LENGTH: &str = "content-length";
 const HEADER_CONTENT_TYPE: &str = "content-type";
 
+/// A diff which has a source file open: its URI, and the session it's open
+/// in, since sessions sharing a backend may use the same URIs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DocumentHolder {
+    pub session: u64,
+    pub diff: Url,
+}
+
+/// A file open in a backend, as it was last sent, and the diffs holding it
+/// open.  It's closed when the last of them lets go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenDocument {
+    pub version: i32,
+    pub text: String,
+    pub holders: HashSet<DocumentHolder>,
+}
+
 #[derive(Debug)]
 pub struct ClientForBackendServer {
     pub lsp_command: String,
+    config: BackendConfig,
     process: Child,
+    // Shared with the reader thread, which answers the backend's requests
+    stdin: Arc<StdMutex<ChildStdin>>,
+    // Responses, in the order the backend sent them
+    responses: Receiver<Value>,
+    // The last diagnostics published for each file
+    diagnostics: Arc<StdMutex<HashMap<Url, Vec<Diagnostic>>>>,
     #[allow(dead_code)]
     path: Option<PathBuf>,
     request_id: i32,
+    workspace_folders: Vec<WorkspaceFolder>,
+    // What the backend said it can do, once it's initialized
+    capabilities: Option<ServerCapabilities>,
+    // Whether `initialized` has been sent.  Backends shared between sessions
+    // must only get it once.
+    is_initialized: bool,
+    // Source files opened with didOpen and not yet closed
+    open_documents: HashMap<Url, OpenDocument>,
+}
+
+/// Names the folder after its last component, as editors do.
+pub fn workspace_folder(path: &Path) -> Option<WorkspaceFolder> {
+    Some(WorkspaceFolder {
+        uri: Url::from_directory_path(path).ok()?,
+        name: path.file_name()?.to_string_lossy().to_string(),
+    })
 }
 
-fn start_server(command: String, args: Option<String>, dir: &str) -> Result<Child> {
-    let mut process = Command::new(&command);
-    if let Some(args_val) = args {
-        process.args(args_val.split_whitespace());
+/// Everything diff-lsp can make use of from a backend.  Only requests it
+/// forwards are declared, so backends don't send anything it would drop.
+pub fn client_capabilities() -> ClientCapabilities {
+    let goto = Some(GotoCapability {
+        dynamic_registration: Some(false),
+        // Links are turned into plain locations when backends are merged
+        link_support: Some(true),
+    });
+    ClientCapabilities {
+        workspace: Some(WorkspaceClientCapabilities {
+            workspace_folders: Some(true),
+            did_change_configuration: Some(DynamicRegistrationClientCapabilities {
+                dynamic_registration: Some(false),
+            }),
+            // Requests from the backend are only ever answered with null
+            configuration: Some(false),
+            ..Default::default()
+        }),
+        text_document: Some(TextDocumentClientCapabilities {
+            synchronization: Some(TextDocumentSyncClientCapabilities {
+                dynamic_registration: Some(false),
+                will_save: Some(false),
+                will_save_wait_until: Some(false),
+                did_save: Some(false),
+            }),
+            hover: Some(HoverClientCapabilities {
+                dynamic_registration: Some(false),
+                content_format: Some(vec![MarkupKind::Markdown, MarkupKind::PlainText]),
+            }),
+            definition: goto,
+            type_definition: goto,
+            references: Some(ReferenceClientCapabilities {
+                dynamic_registration: Some(false),
+            }),
+            publish_diagnostics: Some(PublishDiagnosticsClientCapabilities::default()),
+            ..Default::default()
+        }),
+        general: Some(GeneralClientCapabilities {
+            position_encodings: Some(vec![PositionEncodingKind::UTF16]),
+            ..Default::default()
+        }),
+        window: None,
+        experimental: None,
     }
+}
+
+/// Whether a backend with `capabilities` answers `method`.  Methods diff-lsp
+/// doesn't check, such as notifications, are assumed to be supported.
+pub fn supports_method(capabilities: &ServerCapabilities, method: &str) -> bool {
+    match method {
+        "textDocument/hover" => matches!(
+            capabilities.hover_provider,
+            Some(HoverProviderCapability::Simple(true)) | Some(HoverProviderCapability::Options(_))
+        ),
+        "textDocument/definition" => matches!(
+            capabilities.definition_provider,
+            Some(OneOf::Left(true)) | Some(OneOf::Right(_))
+        ),
+        "textDocument/typeDefinition" => matches!(
+            capabilities.type_definition_provider,
+            Some(TypeDefinitionProviderCapability::Simple(true))
+                | Some(TypeDefinitionProviderCapability::Options(_))
+        ),
+        "textDocument/references" => matches!(
+            capabilities.references_provider,
+            Some(OneOf::Left(true)) | Some(OneOf::Right(_))
+        ),
+        _ => true,
+    }
+}
+
+fn start_server(config: &BackendConfig, dir: &str) -> Result<Child> {
+    let mut process = Command::new(&config.command);
+    process.args(&config.args).envs(&config.env);
     let expanded_dir = expanduser(dir)?;
     let child = process
         .current_dir(canonicalize(expanded_dir)?)
@@ -44,17 +159,41 @@ fn start_server(command: String, args: Option<String>, dir: &str) -> Result<Chil
 
     match child {
         Ok(c) => Ok(c),
-        Err(_) => Err(anyhow!("Failed to spawn local LSP server: {}", command)),
+        Err(_) => Err(anyhow!(
+            "Failed to spawn local LSP server: {}",
+            config.command
+        )),
     }
 }
 
 impl ClientForBackendServer {
-    pub fn new(command: String, args: Option<String>, directory: &str) -> Result<Self> {
+    pub fn new(config: &BackendConfig, directory: &str) -> Result<Self> {
+        let mut process = start_server(config, directory)?;
+        let stdin = Arc::new(StdMutex::new(process.stdin.take().unwrap()));
+        let (sender, responses) = channel();
+        let diagnostics = Arc::new(StdMutex::new(HashMap::new()));
+        let stdout = process.stdout.take().unwrap();
+        let (name, output_stdin, published) =
+            (config.command.clone(), stdin.clone(), diagnostics.clone());
+        thread::spawn(move || read_output(name, stdout, output_stdin, sender, published));
+        let stderr = process.stderr.take().unwrap();
+        let name = config.command.clone();
+        thread::spawn(move || read_errors(name, stderr));
         Ok(ClientForBackendServer {
-            lsp_command: command.clone(),
-            process: start_server(command.clone(), args, directory)?,
+            lsp_command: config.command.clone(),
+            config: config.clone(),
+            process,
+            stdin,
+            responses,
+            diagnostics,
             path: Some(canonicalize(directory)?),
             request_id: 1,
+            workspace_folders: workspace_folder(&canonicalize(directory)?)
+                .into_iter()
+                .collect(),
+            capabilities: None,
+            is_initialized: false,
+            open_documents: HashMap::new(),
         })
     }
 
@@ -76,24 +215,14 @@ impl ClientForBackendServer {
                     .into_string()
                     .unwrap(),
             ),
-            root_uri: None,
-            initialization_options: None,
-            capabilities: ClientCapabilities {
-                workspace: None,
-                text_document: {
-                    Some(TextDocumentClientCapabilities {
-                        hover: Some(HoverClientCapabilities::default()),
-                        //references: Some(ReferenceClientCapabilities{dynamic_registration: None}),
-                        references: None,
-                        ..Default::default()
-                    })
-                },
-                window: None,
-                general: None,
-                experimental: None,
-            },
+            root_uri: self
+                .path
+                .as_ref()
+                .and_then(|path| Url::from_directory_path(path).ok()),
+            initialization_options: self.config.initialization_options.clone(),
+            capabilities: client_capabilities(),
             trace: None,
-            workspace_folders: None,
+            workspace_folders: Some(self.workspace_folders.clone()),
             client_info: Some(ClientInfo {
                 name: "diff-lsp-client".to_string(),
                 version: Some("0.0.1".to_string()),
@@ -102,16 +231,83 @@ impl ClientForBackendServer {
         };
         let method = "initialize".to_string(); // TODO: Is there an enum for this?
                                                // println!("Sending initialize to backend {}", self.lsp_command);
-        let raw_resp = self.request(method, params).unwrap();
-        let resp: InitializeResult = serde_json::from_value(raw_resp).unwrap();
+        let raw_resp = self.request(method, params)?;
+        let resp: InitializeResult = serde_json::from_value(raw_resp)?;
         //println!("We got the response: {resp:?}");
+        info!("{} capabilities: {:?}", self.config.name, resp.capabilities);
+        self.capabilities = Some(resp.capabilities.clone());
 
         return Ok(resp);
     }
 
+    /// What the backend said it can do, or `None` before it's initialized.
+    pub fn capabilities(&self) -> Option<&ServerCapabilities> {
+        self.capabilities.as_ref()
+    }
+
+    /// Whether `method` can be forwarded to the backend.  A backend which
+    /// didn't report its capabilities is given the benefit of the doubt.
+    pub fn supports(&self, method: &str) -> bool {
+        self.capabilities
+            .as_ref()
+            .is_none_or(|capabilities| supports_method(capabilities, method))
+    }
+
+    /// Whether the backend can be told about more workspace folders after
+    /// it has started, rather than needing one process per folder.
+    pub fn supports_workspace_folders(&self) -> bool {
+        self.capabilities
+            .as_ref()
+            .and_then(|capabilities| capabilities.workspace.as_ref())
+            .and_then(|workspace| workspace.workspace_folders.as_ref())
+            .is_some_and(|folders| {
+                folders.supported == Some(true)
+                    && matches!(
+                        folders.change_notifications,
+                        Some(OneOf::Left(true)) | Some(OneOf::Right(_))
+                    )
+            })
+    }
+
+    pub fn workspace_folders(&self) -> &[WorkspaceFolder] {
+        &self.workspace_folders
+    }
+
+    /// Adds `path` as a workspace folder, sending
+    /// `workspace/didChangeWorkspaceFolders` if it's new.
+    pub fn add_workspace_folder(&mut self, path: &Path) {
+        let folder = match workspace_folder(path) {
+            Some(folder) => folder,
+            None => return,
+        };
+        if self.workspace_folders.contains(&folder) {
+            return;
+        }
+        self.workspace_folders.push(folder.clone());
+        self.notify(
+            "workspace/didChangeWorkspaceFolders".to_string(),
+            DidChangeWorkspaceFoldersParams {
+                event: WorkspaceFoldersChangeEvent {
+                    added: vec![folder],
+                    removed: vec![],
+                },
+            },
+        );
+    }
+
     pub fn initialized(&mut self) {
+        if self.is_initialized {
+            return;
+        }
+        self.is_initialized = true;
         // send the initialized notification
-        let _ = self.notify("initialized".to_string(), InitializedParams {});
+        self.notify("initialized".to_string(), InitializedParams {});
+        if let Some(settings) = self.config.settings.clone() {
+            self.notify(
+                "workspace/didChangeConfiguration".to_string(),
+                DidChangeConfigurationParams { settings },
+            );
+        }
     }
 
     fn request<P: Serialize>(&mut self, method: String, params: P) -> Result<Value> {
@@ -120,16 +316,12 @@ impl ClientForBackendServer {
         //     "Sending request {} to backend {}: {}",
         //     method, self.lsp_command, ser_params
         // );
-        let raw_resp = self
-            .send_value_request(ser_params, method.clone(), true)
-            .unwrap();
-        let as_value: Value = serde_json::from_str(&raw_resp).unwrap();
+        let as_value = self.send_value_request(ser_params, method.clone(), true)?;
         // info!("Request result for method: {:?}, {:?}", method, as_value);
-        if let Some(result) = as_value.get("result") {
-            Ok(result.clone())
-        } else {
-            Err(anyhow!("No value"))
+        if let Some(error) = as_value.get("error") {
+            return Err(anyhow!("{} returned an error: {}", self.lsp_command, error));
         }
+        Ok(as_value.get("result").cloned().unwrap_or(Value::Null))
     }
 
     pub fn notify<P: Serialize>(&mut self, method: String, params: P) {
@@ -139,7 +331,9 @@ impl ClientForBackendServer {
             "Sending notification {} to backend {}",
             method, self.lsp_command
         );
-        self.send_value_request(ser_params, method, false).unwrap();
+        if let Err(e) = self.send_value_request(ser_params, method.clone(), false) {
+            info!("Could not send {} to {}: {}", method, self.lsp_command, e);
+        }
     }
 
     fn send_value_request<P: Serialize>(
@@ -147,9 +341,8 @@ impl ClientForBackendServer {
         val: P,
         method: String,
         check_response: bool,
-    ) -> Result<String> {
+    ) -> Result<Value> {
         let id = self.get_request_id();
-        let std_in = self.process.stdin.as_mut().unwrap();
         // Also make the header
         let full_body;
         if check_response {
@@ -166,61 +359,130 @@ impl ClientForBackendServer {
                 "params": &val,
             });
         }
-        let full_binding = serde_json::to_string(&full_body).unwrap();
-        let msg = format!(
-            "Content-Length: {}\r\n\r\n{}",
-            full_binding.len(),
-            full_binding
-        );
         if method.contains("ized") {
-            println!("msg: {}", msg);
+            println!("msg: {}", full_body);
         }
-
-        let _ = std_in.write_all(msg.as_bytes());
-        let _ = std_in.flush();
+        write_message(&self.stdin, &full_body)?;
 
         if !check_response {
-            // // was testing if maybe there was other error output
-            // let std_err = self.process.stderr.as_mut().unwrap();
-            // let mut stderr_reader = BufReader::new(std_err);
-            // let mut body_buffer = vec![0; 200];
-            // let _ = stderr_reader.read(&mut body_buffer);
-            // println!("Backend stderr: {:?}", String::from_utf8(body_buffer));
-            return Ok("".to_string());
+            return Ok(Value::Null);
         }
 
-        let std_out = self.process.stdout.as_mut().unwrap();
-        let mut stdout_reader = BufReader::new(std_out);
-        // let mut stdout_reader = BufReader::new(std_out);
-        //let mut stdout_reader = TimeoutReader::new(std_out, Duration::new(2, 0));
-
-        let resp = read_message(&mut stdout_reader);
-        match resp {
-            Ok(r) => {
-                println!("Okay! {:?}", r);
-                if r.contains("registerCapability") {
-                    println!("Got a register response");
-                    if let Ok(r) = read_message(&mut stdout_reader) {
-                        return Ok(r);
-                    }
+        // Anything left over is the answer to a request that was given up on
+        loop {
+            match self.responses.recv() {
+                Ok(response) if response.get("id") == Some(&json!(id)) => return Ok(response),
+                Ok(response) => info!("{} sent a stale response: {}", self.lsp_command, response),
+                Err(_) => {
+                    return Err(anyhow!(
+                        "{} stopped before answering {}",
+                        self.lsp_command,
+                        method
+                    ))
                 }
-                Ok(r)
-            }
-            Err(e) => {
-                let std_err = self.process.stderr.as_mut().unwrap();
-                let mut stderr_reader = BufReader::new(std_err);
-                let mut body_buffer = vec![0; 200];
-                let _ = stderr_reader.read(&mut body_buffer).unwrap();
-                println!("Backend stderr: {:?}", String::from_utf8(body_buffer));
-                Err(e)
             }
         }
     }
 
+    /// Asks the backend to shut down and exit, making sure it has.
+    pub fn shutdown(&mut self) {
+        if let Err(e) = self.request("shutdown".to_string(), Value::Null) {
+            info!("{} didn't shut down cleanly: {}", self.lsp_command, e);
+        }
+        self.notify("exit".to_string(), Value::Null);
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+
     pub fn did_open(&mut self, params: &DidOpenTextDocumentParams) {
         self.notify("textDocument/didOpen".to_string(), params);
     }
 
+    pub fn did_change(&mut self, params: &DidChangeTextDocumentParams) {
+        self.notify("textDocument/didChange".to_string(), params);
+    }
+
+    pub fn did_close(&mut self, params: &DidCloseTextDocumentParams) {
+        self.notify("textDocument/didClose".to_string(), params);
+    }
+
+    pub fn open_documents(&self) -> &HashMap<Url, OpenDocument> {
+        &self.open_documents
+    }
+
+    /// Holds `uri` open for `holder`.  The first holder opens it; after that
+    /// it's updated to `text` if that has changed.
+    pub fn open_document(
+        &mut self,
+        holder: DocumentHolder,
+        uri: &Url,
+        language_id: &str,
+        text: String,
+    ) {
+        if let Some(document) = self.open_documents.get_mut(uri) {
+            document.holders.insert(holder);
+            self.update_document(uri, text);
+            return;
+        }
+        self.did_open(&DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: language_id.to_string(),
+                version: 1,
+                text: text.clone(),
+            },
+        });
+        self.open_documents.insert(
+            uri.clone(),
+            OpenDocument {
+                version: 1,
+                text,
+                holders: HashSet::from([holder]),
+            },
+        );
+    }
+
+    /// Sends `text` as the next version of `uri` if it's open with other
+    /// contents.  The whole text is sent, which every backend accepts
+    /// whatever sync kind it asked for.
+    pub fn update_document(&mut self, uri: &Url, text: String) {
+        let Some(document) = self.open_documents.get_mut(uri) else {
+            return;
+        };
+        if document.text == text {
+            return;
+        }
+        document.version += 1;
+        document.text = text.clone();
+        let version = document.version;
+        self.did_change(&DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version,
+            },
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text,
+            }],
+        });
+    }
+
+    /// Lets go of `uri` for `holder`, sending didClose if nothing else holds
+    /// it open.
+    pub fn close_document(&mut self, holder: &DocumentHolder, uri: &Url) {
+        let Some(document) = self.open_documents.get_mut(uri) else {
+            return;
+        };
+        document.holders.remove(holder);
+        if document.holders.is_empty() {
+            self.open_documents.remove(uri);
+            self.did_close(&DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+            });
+        }
+    }
+
     pub fn hover(&mut self, params: HoverParams) -> Result<Option<Hover>> {
         println!("Doing hover with teh params: {:?}", params);
         let res = self.request("textDocument/hover".to_string(), params);
@@ -237,7 +499,7 @@ impl ClientForBackendServer {
                     Err(_) => return Ok(None),
                 }
             }
-            Err(_) => return Ok(None),
+            Err(e) => return Err(e),
         }
     }
 
@@ -261,7 +523,7 @@ impl ClientForBackendServer {
                     Err(_) => return Ok(None),
                 }
             }
-            Err(_) => return Ok(None),
+            Err(e) => return Err(e),
         }
     }
 
@@ -285,7 +547,7 @@ impl ClientForBackendServer {
                     Err(_) => return Ok(None),
                 }
             }
-            Err(_) => return Ok(None),
+            Err(e) => return Err(e),
         }
     }
 
@@ -306,25 +568,96 @@ impl ClientForBackendServer {
                     Err(_) => return Ok(None),
                 }
             }
-            Err(_) => return Ok(None),
+            Err(e) => return Err(e),
         }
     }
 
-    pub fn check_messages(&mut self) {
-        let std_out = self.process.stdout.as_mut().unwrap();
-        let mut stdout_reader = BufReader::new(std_out);
-        let resp = read_message(&mut stdout_reader);
-        match resp {
-            Ok(r) => {
-                info!("{}", r)
-            }
+    /// The diagnostics the backend last published for each file.
+    pub fn diagnostics(&self) -> HashMap<Url, Vec<Diagnostic>> {
+        self.diagnostics.lock().unwrap().clone()
+    }
+
+    pub fn check_messages(&self) {
+        for (uri, diagnostics) in self.diagnostics.lock().unwrap().iter() {
+            info!(
+                "{} has {} diagnostics for {}",
+                self.lsp_command,
+                diagnostics.len(),
+                uri
+            );
+        }
+    }
+}
+
+/// Reads everything the backend sends until it exits.  Responses are passed
+/// on to the request waiting for them, published diagnostics are kept, and
+/// requests from the backend are answered with null so it isn't left waiting.
+fn read_output(
+    name: String,
+    stdout: ChildStdout,
+    stdin: Arc<StdMutex<ChildStdin>>,
+    responses: Sender<Value>,
+    diagnostics: Arc<StdMutex<HashMap<Url, Vec<Diagnostic>>>>,
+) {
+    let mut reader = BufReader::new(stdout);
+    loop {
+        let message = match read_message(&mut reader) {
+            Ok(body) => match serde_json::from_str::<Value>(&body) {
+                Ok(message) => message,
+                Err(e) => {
+                    info!("{} sent something unreadable: {}", name, e);
+                    continue;
+                }
+            },
             Err(e) => {
-                info!("{}", e)
+                info!("Stopped reading from {}: {}", name, e);
+                return;
+            }
+        };
+        let method = message.get("method").and_then(Value::as_str);
+        match (message.get("id"), method) {
+            (Some(_), None) => {
+                if responses.send(message).is_err() {
+                    return;
+                }
+            }
+            (Some(id), Some(method)) => {
+                info!("{} asked for {}, answering null", name, method);
+                let response = json!({"jsonrpc": "2.0", "id": id, "result": null});
+                let _ = write_message(&stdin, &response);
+            }
+            (None, Some("textDocument/publishDiagnostics")) => {
+                match serde_json::from_value::<PublishDiagnosticsParams>(message["params"].clone())
+                {
+                    Ok(params) => {
+                        diagnostics
+                            .lock()
+                            .unwrap()
+                            .insert(params.uri, params.diagnostics);
+                    }
+                    Err(e) => info!("{} published unreadable diagnostics: {}", name, e),
+                }
             }
+            (None, _) => info!("{}: {}", name, message),
         }
     }
 }
 
+/// Logs what the backend writes to stderr, so it never blocks on a full pipe.
+fn read_errors(name: String, stderr: ChildStderr) {
+    for line in BufReader::new(stderr).lines().map_while(|line| line.ok()) {
+        info!("{} stderr: {}", name, line);
+    }
+}
+
+fn write_message(stdin: &StdMutex<ChildStdin>, message: &Value) -> Result<()> {
+    let body = serde_json::to_string(message)?;
+    let mut stdin = stdin.lock().unwrap();
+    stdin.write_all(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes())?;
+    stdin.flush()?;
+    Ok(())
+}
+
 pub enum LspHeader {
     ContentType,
     ContentLength(usize),
@@ -376,16 +709,5 @@ pub fn read_message<T: BufRead>(reader: &mut T) -> Result<String> {
     let mut body_buffer = vec![0; content_length];
     reader.read_exact(&mut body_buffer)?;
 
-    let body = String::from_utf8(body_buffer)?;
-    // we don't want this for now
-    if body.contains("showMessage")
-        || body.contains("logMessage")
-        || body.contains("publishDiagnostics")
-    {
-        info!("{}", body);
-        read_message(reader)
-    } else {
-        // info!("MISC body {}", body);
-        Ok(body)
-    }
+    Ok(String::from_utf8(body_buffer)?)
 }
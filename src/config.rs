@@ -0,0 +1,536 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use expanduser::expanduser;
+use glob::Pattern;
+use log::info;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// The languages diff-lsp knows about out of the box.  The config file is
+/// layered on top of these, so it only needs to mention what it changes.
+const DEFAULT_CONFIG: &str = r#"
+[language.rust]
+extensions = ["rs"]
+root_markers = ["Cargo.toml"]
+command = "rust-analyzer"
+
+[language.go]
+extensions = ["go"]
+root_markers = ["go.mod"]
+command = "gopls"
+
+[language.python]
+extensions = ["py", "pyi"]
+interpreters = ["python"]
+root_markers = ["pyproject.toml", "setup.py"]
+command = "pylsp"
+
+[language.typescript]
+extensions = ["ts", "tsx", "mts", "cts"]
+root_markers = ["tsconfig.json", "package.json"]
+command = "typescript-language-server"
+args = ["--stdio"]
+
+[language.javascript]
+extensions = ["js", "jsx", "mjs", "cjs"]
+interpreters = ["node"]
+root_markers = ["jsconfig.json", "package.json"]
+command = "typescript-language-server"
+args = ["--stdio"]
+
+[language.cpp]
+extensions = ["c", "h", "cc", "cpp", "cxx", "hh", "hpp", "hxx"]
+root_markers = ["compile_commands.json", "CMakeLists.txt", ".clangd"]
+command = "clangd"
+
+[language.java]
+extensions = ["java"]
+root_markers = ["pom.xml", "build.gradle", "build.gradle.kts"]
+command = "jdtls"
+
+[language.kotlin]
+extensions = ["kt", "kts"]
+root_markers = ["settings.gradle.kts", "build.gradle.kts", "pom.xml"]
+command = "kotlin-language-server"
+
+[language.ruby]
+extensions = ["rb", "rake", "gemspec"]
+filenames = ["Gemfile", "Rakefile"]
+interpreters = ["ruby"]
+root_markers = ["Gemfile"]
+command = "ruby-lsp"
+
+[language.lua]
+extensions = ["lua"]
+interpreters = ["lua", "luajit"]
+root_markers = [".luarc.json"]
+command = "lua-language-server"
+
+[language.zig]
+extensions = ["zig"]
+root_markers = ["build.zig"]
+command = "zls"
+
+[language.haskell]
+extensions = ["hs", "lhs"]
+root_markers = ["stack.yaml", "cabal.project", "package.yaml"]
+command = "haskell-language-server-wrapper"
+args = ["--lsp"]
+
+[language.bash]
+extensions = ["sh", "bash"]
+interpreters = ["sh", "bash"]
+command = "bash-language-server"
+args = ["start"]
+
+[language.make]
+extensions = ["mk"]
+filenames = ["Makefile", "makefile", "GNUmakefile"]
+command = "autotools-language-server"
+
+[language.dockerfile]
+extensions = ["dockerfile"]
+filenames = ["Dockerfile", "Containerfile"]
+globs = ["Dockerfile.*"]
+command = "docker-langserver"
+args = ["--stdio"]
+
+[language.starlark]
+extensions = ["bzl", "star"]
+filenames = ["BUILD", "BUILD.bazel", "WORKSPACE", "WORKSPACE.bazel", "MODULE.bazel"]
+root_markers = ["WORKSPACE", "WORKSPACE.bazel", "MODULE.bazel"]
+command = "starpls"
+"#;
+
+/// Where the config file is read from when no other path is given.
+pub fn default_config_path() -> PathBuf {
+    match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("diff-lsp/config.toml"),
+        _ => expanduser("~/.config/diff-lsp/config.toml").unwrap(),
+    }
+}
+
+/// The config file as written.  Every field is optional so that a language
+/// can override part of a built-in one, such as pinning the command.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    language: BTreeMap<String, LanguageConfig>,
+    fetch: Option<FetchConfig>,
+    worktrees: Option<WorktreeConfig>,
+    hunks: Option<HunkConfig>,
+}
+
+/// When and what to fetch from the remote of the repository at the root, so
+/// reviews can be checked against the latest upstream code.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchConfig {
+    /// Fetch on startup, on `refresh` and every `interval`
+    pub enabled: bool,
+    /// The remote to fetch, `origin` for git and the VCS's default otherwise
+    pub remote: Option<String>,
+    /// What to fetch from the remote instead of everything
+    pub refspecs: Vec<String>,
+    /// Seconds between fetches while a session is open.  None only fetches on
+    /// startup and `refresh`.
+    pub interval: Option<u64>,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            enabled: true,
+            remote: None,
+            refspecs: vec![],
+            interval: None,
+        }
+    }
+}
+
+/// Whether reviews of branches which aren't checked out get a git worktree of
+/// their own, so backends see the branch without disturbing the checkout.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorktreeConfig {
+    pub enabled: bool,
+    /// Where review worktrees are made, `~/.cache/diff-lsp/worktrees` by
+    /// default
+    pub dir: Option<String>,
+    /// How many review worktrees to keep per repository.  The least recently
+    /// used are removed beyond this.
+    pub max: usize,
+}
+
+impl Default for WorktreeConfig {
+    fn default() -> Self {
+        WorktreeConfig {
+            enabled: false,
+            dir: None,
+            max: 3,
+        }
+    }
+}
+
+/// How hunks are checked against the files they're from.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HunkConfig {
+    /// Map a hunk found a few lines from where the diff says to where it is,
+    /// rather than only warning about it
+    pub realign: bool,
+    /// How many lines either side of where the diff says to look for a hunk
+    pub distance: usize,
+    /// How many context lines at either end of a hunk may differ from the
+    /// file, as with `patch --fuzz`
+    pub fuzz: usize,
+}
+
+impl Default for HunkConfig {
+    fn default() -> Self {
+        HunkConfig {
+            realign: true,
+            distance: 50,
+            fuzz: 2,
+        }
+    }
+}
+
+/// Everything read from the config file.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub languages: LanguageRegistry,
+    pub fetch: FetchConfig,
+    pub worktrees: WorktreeConfig,
+    pub hunks: HunkConfig,
+}
+
+impl Config {
+    /// Reads the config file at `path`, or the default location if None.  A
+    /// missing file at the default location just means the defaults are used.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let path = default_config_path();
+                if !path.exists() {
+                    info!("No config file at {:?}, using defaults", path);
+                    return Ok(Config::default());
+                }
+                path
+            }
+        };
+        info!("Loading config from {:?}", path);
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Unable to read config file {:?}: {}", path, e))?;
+        Config::from_toml(&contents).map_err(|e| anyhow!("Invalid config file {:?}: {}", path, e))
+    }
+
+    /// The defaults with `source` layered on top.
+    pub fn from_toml(source: &str) -> Result<Self> {
+        let mut config_file: ConfigFile = toml::from_str(source)?;
+        let fetch = config_file.fetch.take().unwrap_or_default();
+        let worktrees = config_file.worktrees.take().unwrap_or_default();
+        let hunks = config_file.hunks.take().unwrap_or_default();
+        let mut languages = LanguageRegistry { languages: vec![] };
+        languages.merge(toml::from_str(DEFAULT_CONFIG)?)?;
+        languages.merge(config_file)?;
+        Ok(Config {
+            languages,
+            fetch,
+            worktrees,
+            hunks,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct LanguageConfig {
+    extensions: Option<Vec<String>>,
+    filenames: Option<Vec<String>>,
+    globs: Option<Vec<String>>,
+    root_markers: Option<Vec<String>>,
+    interpreters: Option<Vec<String>>,
+    // A single backend can be written inline, several need `backends`
+    command: Option<String>,
+    args: Option<Vec<String>>,
+    env: Option<HashMap<String, String>>,
+    initialization_options: Option<Value>,
+    settings: Option<Value>,
+    backends: Option<Vec<BackendConfig>>,
+}
+
+/// How to start and set up a language server.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BackendConfig {
+    /// Defaults to the command
+    #[serde(default)]
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Sent as `initializationOptions` in the backend's `initialize` request
+    pub initialization_options: Option<Value>,
+    /// Sent with `workspace/didChangeConfiguration` once the backend is initialized
+    pub settings: Option<Value>,
+    /// The LSP requests to send to this backend, such as `textDocument/hover`.
+    /// Empty means all of them.
+    #[serde(default)]
+    pub methods: Vec<String>,
+}
+
+impl BackendConfig {
+    pub fn handles(&self, method: &str) -> bool {
+        self.methods.is_empty() || self.methods.iter().any(|m| m == method)
+    }
+}
+
+/// A language, the files which belong to it and the backends which serve them.
+#[derive(Debug, Clone, Default)]
+pub struct Language {
+    pub name: String,
+    pub extensions: Vec<String>,
+    /// Exact file names such as `Makefile`
+    pub filenames: Vec<String>,
+    pub globs: Vec<Pattern>,
+    /// Files such as `go.mod` which mark the root of a project
+    pub root_markers: Vec<String>,
+    /// Programs named in the `#!` line of scripts without an extension
+    pub interpreters: Vec<String>,
+    /// In order of preference, for requests where only one answer is used
+    pub backends: Vec<BackendConfig>,
+}
+
+impl Language {
+    fn merge(&mut self, config: LanguageConfig) -> Result<()> {
+        if let Some(extensions) = config.extensions {
+            self.extensions = extensions;
+        }
+        if let Some(filenames) = config.filenames {
+            self.filenames = filenames;
+        }
+        if let Some(globs) = config.globs {
+            self.globs = globs
+                .iter()
+                .map(|glob| {
+                    Pattern::new(glob)
+                        .map_err(|e| anyhow!("Invalid glob {:?} for {}: {}", glob, self.name, e))
+                })
+                .collect::<Result<_>>()?;
+        }
+        if let Some(root_markers) = config.root_markers {
+            self.root_markers = root_markers;
+        }
+        if let Some(interpreters) = config.interpreters {
+            self.interpreters = interpreters;
+        }
+        if let Some(backends) = config.backends {
+            if config.command.is_some() {
+                return Err(anyhow!(
+                    "Language {} sets both command and backends, use one or the other",
+                    self.name
+                ));
+            }
+            self.backends = backends;
+        }
+
+        // Inline settings change the preferred backend
+        let inline = config.command.is_some()
+            || config.args.is_some()
+            || config.env.is_some()
+            || config.initialization_options.is_some()
+            || config.settings.is_some();
+        if inline && self.backends.is_empty() {
+            self.backends.push(BackendConfig::default());
+        }
+        if let Some(backend) = self.backends.first_mut() {
+            if let Some(command) = config.command {
+                backend.command = command;
+                // The name of a built-in backend follows its command
+                backend.name = String::new();
+            }
+            if let Some(args) = config.args {
+                backend.args = args;
+            }
+            if let Some(env) = config.env {
+                backend.env = env;
+            }
+            if let Some(options) = config.initialization_options {
+                backend.initialization_options = Some(options);
+            }
+            if let Some(settings) = config.settings {
+                backend.settings = Some(settings);
+            }
+        }
+
+        for backend in self.backends.iter_mut() {
+            if backend.name.is_empty() {
+                backend.name = backend.command.clone();
+            }
+        }
+        Ok(())
+    }
+
+    /// The nearest directory above `file`, a path relative to `workspace_root`,
+    /// which has one of the root markers.  Falls back to `workspace_root` if
+    /// there's none below it.
+    pub fn project_root(&self, workspace_root: &str, file: &str) -> String {
+        let workspace = Path::new(workspace_root);
+        let mut dir = workspace.join(file.trim_start_matches('/'));
+        while dir.pop() && dir.starts_with(workspace) {
+            if dir == workspace {
+                break;
+            }
+            if self
+                .root_markers
+                .iter()
+                .any(|marker| dir.join(marker).exists())
+            {
+                return dir.display().to_string();
+            }
+        }
+        workspace_root.to_string()
+    }
+
+    fn matches_filename(&self, path: &str) -> bool {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        self.filenames.iter().any(|name| name == file_name)
+    }
+
+    fn matches_glob(&self, path: &str) -> bool {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        self.globs
+            .iter()
+            .any(|glob| glob.matches(path) || glob.matches(file_name))
+    }
+
+    /// The length of the longest extension of this language `path` has, so
+    /// that `d.ts` can be told apart from `ts`.
+    fn extension_match(&self, path: &str) -> Option<usize> {
+        self.extensions
+            .iter()
+            .filter(|extension| path.ends_with(&format!(".{}", extension)))
+            .map(|extension| extension.len())
+            .max()
+    }
+}
+
+/// The interpreter a `#!` line runs, without any version, so that both
+/// `#!/usr/bin/python3.11` and `#!/usr/bin/env -S python3 -u` give `python`.
+pub fn shebang_interpreter(line: &str) -> Option<String> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+    let name = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Every language diff-lsp can find a backend for, from the built-in defaults
+/// and the config file.
+#[derive(Debug, Clone)]
+pub struct LanguageRegistry {
+    languages: Vec<Language>,
+}
+
+impl Default for LanguageRegistry {
+    fn default() -> Self {
+        LanguageRegistry::from_toml("").unwrap()
+    }
+}
+
+impl LanguageRegistry {
+    /// The languages of the config file at `path`, see `Config::load`.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        Config::load(path).map(|config| config.languages)
+    }
+
+    /// Builds the registry from the defaults with `source` layered on top.
+    pub fn from_toml(source: &str) -> Result<Self> {
+        Config::from_toml(source).map(|config| config.languages)
+    }
+
+    fn merge(&mut self, config: ConfigFile) -> Result<()> {
+        for (name, language_config) in config.language {
+            match self.languages.iter_mut().find(|l| l.name == name) {
+                Some(language) => language.merge(language_config)?,
+                None => {
+                    let mut language = Language {
+                        name: name.clone(),
+                        ..Default::default()
+                    };
+                    language.merge(language_config)?;
+                    if language.backends.iter().all(|b| b.command.is_empty()) {
+                        return Err(anyhow!("Language {} has no command", name));
+                    }
+                    self.languages.push(language);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn languages(&self) -> &[Language] {
+        &self.languages
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Language> {
+        self.languages.iter().find(|language| language.name == name)
+    }
+
+    /// Finds the language of `file`, a path relative to `root`.  Files which
+    /// can't be told apart by name, such as scripts without an extension, are
+    /// read from disk for a `#!` line.
+    pub fn detect(&self, root: &str, file: &str) -> Option<&Language> {
+        self.language_for(file).or_else(|| {
+            let path = Path::new(root).join(file.trim_start_matches('/'));
+            let mut start = vec![];
+            fs::File::open(path)
+                .ok()?
+                .take(256)
+                .read_to_end(&mut start)
+                .ok()?;
+            let first_line = start.split(|&b| b == b'\n').next()?;
+            self.language_for_shebang(&String::from_utf8_lossy(first_line))
+        })
+    }
+
+    pub fn language_for_shebang(&self, line: &str) -> Option<&Language> {
+        let interpreter = shebang_interpreter(line)?;
+        self.languages
+            .iter()
+            .find(|language| language.interpreters.contains(&interpreter))
+    }
+
+    /// Finds the language of a file in the diff.  Exact file names win over
+    /// globs, which win over extensions.
+    pub fn language_for(&self, path: &str) -> Option<&Language> {
+        self.languages
+            .iter()
+            .find(|language| language.matches_filename(path))
+            .or_else(|| {
+                self.languages
+                    .iter()
+                    .find(|language| language.matches_glob(path))
+            })
+            .or_else(|| {
+                // Reversed so that ties go to the first language
+                self.languages
+                    .iter()
+                    .rev()
+                    .filter_map(|language| Some((language, language.extension_match(path)?)))
+                    .max_by_key(|(_, length)| *length)
+                    .map(|(language, _)| language)
+            })
+    }
+}
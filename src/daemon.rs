@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use expanduser::expanduser;
+use log::info;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::Mutex;
+use tower_lsp::{Client, LspService, Server};
+
+use crate::config::{FetchConfig, HunkConfig, LanguageRegistry, WorktreeConfig};
+use crate::server::{DiffLsp, DiffRoots, SharedBackends, StartingBackends};
+
+/// Where a daemon listens and proxies connect.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DaemonAddress {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+/// `$XDG_RUNTIME_DIR/diff-lsp.sock`, which only the user can get at, falling
+/// back to `~/.diff-lsp.sock`.
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("diff-lsp.sock"),
+        _ => expanduser("~/.diff-lsp.sock").unwrap(),
+    }
+}
+
+impl DaemonAddress {
+    pub fn new(socket: Option<PathBuf>, tcp: Option<String>) -> Self {
+        match tcp {
+            Some(address) => DaemonAddress::Tcp(address),
+            None => DaemonAddress::Unix(socket.unwrap_or_else(default_socket_path)),
+        }
+    }
+}
+
+/// A long-lived diff-lsp serving many editor sessions.  Backends are kept
+/// running between sessions, so reopening a review doesn't mean waiting for
+/// rust-analyzer or gopls to index the project again.
+#[derive(Debug, Clone)]
+pub struct Daemon {
+    pub backends: SharedBackends,
+    pub starting: StartingBackends,
+    pub diff_roots: DiffRoots,
+    pub languages: LanguageRegistry,
+    pub fetch: FetchConfig,
+    pub worktrees: WorktreeConfig,
+    pub hunks: HunkConfig,
+}
+
+impl Daemon {
+    pub fn new(languages: LanguageRegistry) -> Self {
+        Daemon {
+            backends: Arc::new(Mutex::new(HashMap::new())),
+            starting: Arc::new(Mutex::new(HashMap::new())),
+            diff_roots: Arc::new(Mutex::new(HashMap::new())),
+            languages,
+            fetch: FetchConfig::default(),
+            worktrees: WorktreeConfig::default(),
+            hunks: HunkConfig::default(),
+        }
+    }
+
+    /// A server for one editor connection.  Its root comes from the editor's
+    /// `initialize`.
+    pub fn session(&self, client: Client) -> DiffLsp {
+        let mut diff_lsp = DiffLsp::with_shared_backends(
+            client,
+            self.backends.clone(),
+            String::new(),
+            self.languages.clone(),
+        );
+        diff_lsp.starting = self.starting.clone();
+        diff_lsp.diff_roots = self.diff_roots.clone();
+        diff_lsp.fetch = self.fetch.clone();
+        diff_lsp.worktrees = self.worktrees.clone();
+        diff_lsp.hunks = self.hunks.clone();
+        diff_lsp
+    }
+
+    fn serve<S>(&self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (service, socket) = LspService::new(|client| self.session(client));
+        tokio::spawn(async move {
+            let (read, write) = tokio::io::split(stream);
+            Server::new(read, write, socket).serve(service).await;
+            info!("Session ended");
+        });
+    }
+
+    /// Accepts sessions on `address` until the process is stopped.
+    pub async fn run(&self, address: &DaemonAddress) -> Result<()> {
+        match address {
+            DaemonAddress::Tcp(address) => {
+                let listener = TcpListener::bind(address).await?;
+                info!("Daemon listening on {}", address);
+                loop {
+                    let (stream, peer) = listener.accept().await?;
+                    info!("Session from {}", peer);
+                    self.serve(stream);
+                }
+            }
+            DaemonAddress::Unix(path) => {
+                let listener = bind_unix(path).await?;
+                info!("Daemon listening on {:?}", path);
+                loop {
+                    let (stream, _) = listener.accept().await?;
+                    info!("Session on {:?}", path);
+                    self.serve(stream);
+                }
+            }
+        }
+    }
+}
+
+async fn bind_unix(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(anyhow!("A daemon is already listening on {:?}", path));
+        }
+        // Left behind by a daemon which didn't exit cleanly
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+async fn pipe<S>(stream: S) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (mut read, mut write) = tokio::io::split(stream);
+    let (mut stdin, mut stdout) = (tokio::io::stdin(), tokio::io::stdout());
+    tokio::select! {
+        sent = tokio::io::copy(&mut stdin, &mut write) => {
+            sent?;
+            write.shutdown().await?;
+        }
+        received = tokio::io::copy(&mut read, &mut stdout) => {
+            received?;
+        }
+    }
+    Ok(())
+}
+
+/// Connects stdin and stdout to the daemon at `address`, for editors which can
+/// only start a command.  When nothing is listening on a Unix socket,
+/// `start_daemon` is run and given a few seconds to start listening.
+pub async fn proxy(address: &DaemonAddress, start_daemon: Option<Command>) -> Result<()> {
+    match address {
+        DaemonAddress::Tcp(address) => pipe(TcpStream::connect(address).await?).await,
+        DaemonAddress::Unix(path) => {
+            if let Ok(stream) = UnixStream::connect(path).await {
+                return pipe(stream).await;
+            }
+            let mut command =
+                start_daemon.ok_or_else(|| anyhow!("No daemon is listening on {:?}", path))?;
+            info!("Starting a daemon: {:?}", command);
+            command.spawn()?;
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                if let Ok(stream) = UnixStream::connect(path).await {
+                    return pipe(stream).await;
+                }
+            }
+            Err(anyhow!("The daemon didn't start listening on {:?}", path))
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use tower_lsp::lsp_types::{Position, TextDocumentContentChangeEvent};
+
+/// The byte offset of `position` in `text`, counting characters in UTF-16
+/// code units as LSP does.  Positions past the end of a line or of the text
+/// are clamped to it.
+pub fn position_to_offset(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(newline) => line_start += newline + 1,
+            None => return text.len(),
+        }
+    }
+    let line = &text[line_start..];
+    let line_end = line.find('\n').unwrap_or(line.len());
+    let mut units = 0;
+    for (offset, c) in line[..line_end].char_indices() {
+        if units >= position.character as usize {
+            return line_start + offset;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line_end
+}
+
+/// Applies one `textDocument/didChange` change: a replaced range for
+/// incremental sync, or the whole text for full sync.
+pub fn apply_change(text: &mut String, change: &TextDocumentContentChangeEvent) {
+    match change.range {
+        Some(range) => {
+            let start = position_to_offset(text, range.start);
+            let end = position_to_offset(text, range.end).max(start);
+            text.replace_range(start..end, &change.text);
+        }
+        None => *text = change.text.clone(),
+    }
+}
@@ -0,0 +1,152 @@
+use std::env;
+use std::fmt::Write as _;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use expanduser::expanduser;
+use log::info;
+
+use crate::config::{BackendConfig, LanguageRegistry};
+
+// Some backends ignore --version and wait on stdin, so don't wait forever
+const VERSION_TIMEOUT: Duration = Duration::from_secs(3);
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Resolves a backend command the way spawning it would: commands with a
+/// slash are paths, anything else is looked up on $PATH.
+pub fn find_executable(command: &str) -> Option<PathBuf> {
+    if command.contains('/') {
+        let path = expanduser(command).ok()?;
+        return is_executable(&path).then_some(path);
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(command))
+        .find(|path| is_executable(path))
+}
+
+/// The first line a backend prints for `--version`, if it does so promptly.
+/// A failing run gives its output as the error, e.g. a rustup proxy for a
+/// component which isn't installed.
+pub fn backend_version(path: &Path) -> Option<Result<String, String>> {
+    let mut child = Command::new(path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().ok()? {
+            break status;
+        }
+        if started.elapsed() > VERSION_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        sleep(Duration::from_millis(20));
+    };
+    // Plenty of servers print their version to stderr
+    let mut output = String::new();
+    child.stdout.take()?.read_to_string(&mut output).ok()?;
+    child.stderr.take()?.read_to_string(&mut output).ok()?;
+    let first_line = output
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())?
+        .to_string();
+    Some(if status.success() {
+        Ok(first_line)
+    } else {
+        Err(first_line)
+    })
+}
+
+/// Whether a configured backend can be started, and which build it is.
+#[derive(Debug, Clone)]
+pub struct BackendStatus {
+    pub language: String,
+    pub name: String,
+    pub command: String,
+    pub path: Option<PathBuf>,
+    pub version: Option<String>,
+    // What `--version` printed when it failed.  Not every server has a
+    // --version, so this is only a hint that something is wrong.
+    pub error: Option<String>,
+}
+
+impl BackendStatus {
+    pub fn check(language: &str, backend: &BackendConfig) -> Self {
+        let path = find_executable(&backend.command);
+        let (version, error) = match path.as_deref().and_then(backend_version) {
+            Some(Ok(version)) => (Some(version), None),
+            Some(Err(error)) => (None, Some(error)),
+            None => (None, None),
+        };
+        BackendStatus {
+            language: language.to_string(),
+            name: backend.name.clone(),
+            command: backend.command.clone(),
+            path,
+            version,
+            error,
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.path.is_some()
+    }
+}
+
+/// Checks every backend of every configured language.
+pub fn check_backends(languages: &LanguageRegistry) -> Vec<BackendStatus> {
+    let mut statuses = vec![];
+    for language in languages.languages() {
+        for backend in language.backends.iter() {
+            let status = BackendStatus::check(&language.name, backend);
+            info!("Backend status: {:?}", status);
+            statuses.push(status);
+        }
+    }
+    statuses
+}
+
+/// The report `diff-lsp doctor` prints, one backend per line.
+pub fn report(statuses: &[BackendStatus]) -> String {
+    let mut out = String::new();
+    for status in statuses {
+        let (mark, found) = match (&status.path, &status.version, &status.error) {
+            (None, _, _) => ("missing", format!("not found: {}", status.command)),
+            (Some(path), _, Some(error)) => ("error", format!("{}: {}", path.display(), error)),
+            (Some(path), Some(version), None) => {
+                ("ok", format!("{} ({})", path.display(), version))
+            }
+            (Some(path), None, None) => ("ok", path.display().to_string()),
+        };
+        let _ = writeln!(
+            out,
+            "{:<8} {:<12} {:<32} {}",
+            mark, status.language, status.name, found
+        );
+    }
+    let available = statuses
+        .iter()
+        .filter(|status| status.is_available())
+        .count();
+    let _ = writeln!(
+        out,
+        "{} of {} backends available",
+        available,
+        statuses.len()
+    );
+    out
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+
+use crate::parsers::utils::{DiffLine, InputLineNumber, LineType, ParsedDiff};
+
+/// A run of consecutive diff lines from one file, between hunk headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub filename: String,
+    pub lines: Vec<(InputLineNumber, DiffLine)>,
+}
+
+/// How a hunk compares with the file it's from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HunkStatus {
+    /// The file has the hunk's lines where the diff says
+    Current,
+    /// The file has the hunk's lines this many lines further down, or up if
+    /// negative
+    Moved(i32),
+    /// The hunk's lines aren't in the file near where the diff says
+    Stale,
+}
+
+/// The hunks of `diff`, in the order they appear in it.  The header lines
+/// between hunks aren't in `lines_map`, so a gap in the input line numbers, or
+/// a change of file, starts a new hunk.
+pub fn hunks(diff: &ParsedDiff) -> Vec<Hunk> {
+    let sorted: BTreeMap<&InputLineNumber, &(String, DiffLine)> = diff.lines_map.iter().collect();
+    let mut hunks: Vec<Hunk> = vec![];
+    for (input_line, (filename, diff_line)) in sorted {
+        // The blank line after a hunk is mapped too, though it isn't in it
+        if diff_line.line.is_empty() {
+            continue;
+        }
+        let continues = hunks.last().is_some_and(|hunk| {
+            hunk.filename == *filename
+                && hunk
+                    .lines
+                    .last()
+                    .is_some_and(|(last, _)| last.0 + 1 == input_line.0)
+        });
+        if !continues {
+            hunks.push(Hunk {
+                filename: filename.clone(),
+                lines: vec![],
+            });
+        }
+        hunks
+            .last_mut()
+            .unwrap()
+            .lines
+            .push((*input_line, diff_line.clone()));
+    }
+    hunks
+}
+
+impl Hunk {
+    pub fn first_line(&self) -> InputLineNumber {
+        self.lines[0].0
+    }
+
+    pub fn last_line(&self) -> InputLineNumber {
+        self.lines[self.lines.len() - 1].0
+    }
+
+    /// Whether `file_lines` has each added and context line of the hunk
+    /// `offset` lines away from where the diff puts it.  Like `patch`, up to
+    /// `fuzz` context lines at the start and end of the hunk may differ.
+    /// Whitespace at either end is ignored, as some formats don't keep it.
+    pub fn matches_at(&self, file_lines: &[&str], offset: i32, fuzz: usize) -> bool {
+        let kept: Vec<&DiffLine> = self
+            .lines
+            .iter()
+            .map(|(_, line)| line)
+            .filter(|line| line.line_type != LineType::Removed)
+            .collect();
+        let is_context = |line: &&&DiffLine| line.line_type == LineType::Unmodified;
+        let leading = kept.iter().take_while(is_context).count().min(fuzz);
+        let trailing = kept.iter().rev().take_while(is_context).count().min(fuzz);
+        if leading + trailing >= kept.len() && !kept.is_empty() {
+            return false;
+        }
+        kept[leading..kept.len() - trailing].iter().all(|line| {
+            let index = line.source_line_number.0 as i32 - 1 + offset;
+            index >= 0
+                && file_lines
+                    .get(index as usize)
+                    .is_some_and(|file_line| file_line.trim() == line.content().trim())
+        })
+    }
+
+    /// The best offset for the hunk in `file_lines`, looking up to `distance`
+    /// lines either side of where the diff says.  As with `patch`, every
+    /// offset is tried before any fuzz is, then the nearest offset wins, and
+    /// down before up.
+    pub fn relocate(&self, file_lines: &[&str], distance: usize, fuzz: usize) -> Option<i32> {
+        let offsets =
+            std::iter::once(0).chain((1..=distance as i32).flat_map(|offset| [offset, -offset]));
+        (0..=fuzz).find_map(|fuzz| {
+            offsets
+                .clone()
+                .find(|offset| self.matches_at(file_lines, *offset, fuzz))
+        })
+    }
+
+    /// Compares the hunk with `file_lines`, see [`Hunk::relocate`].
+    pub fn check(&self, file_lines: &[&str], distance: usize, fuzz: usize) -> HunkStatus {
+        match self.relocate(file_lines, distance, fuzz) {
+            Some(0) => HunkStatus::Current,
+            Some(offset) => HunkStatus::Moved(offset),
+            None => HunkStatus::Stale,
+        }
+    }
+}
+
+/// Maps the lines of `hunk` in `diff` `offset` lines from where the diff puts
+/// them, where the file has them now.
+pub fn realign(diff: &mut ParsedDiff, hunk: &Hunk, offset: i32) {
+    for (input_line, _) in hunk.lines.iter() {
+        if offset == 0 {
+            diff.offsets.remove(input_line);
+        } else {
+            diff.offsets.insert(*input_line, offset);
+        }
+    }
+}
@@ -1,28 +1,39 @@
-use std::fs::{remove_file, OpenOptions};
+use std::fs::{read_to_string, remove_file, OpenOptions};
 
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::OnceLock;
 
 use chrono::Local;
+use clap::Parser;
 use expanduser::expanduser;
-use log::{info, Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use log::{info, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use tokio::net::TcpListener;
 use tower_lsp::{LspService, Server};
 
-use diff_lsp::server::{create_backends_map, read_initialization_params_from_tempfile, DiffLsp};
-use diff_lsp::utils::{fetch_origin_nonblocking, get_most_recent_file};
+use diff_lsp::cli::{Cli, Command};
+use diff_lsp::config::{default_config_path, Config, LanguageRegistry};
+use diff_lsp::daemon::{proxy, Daemon, DaemonAddress};
+use diff_lsp::health::{check_backends, report};
+use diff_lsp::parsers::review::ReviewExport;
+use diff_lsp::server::{
+    self, create_backends_map, read_initialization_params_from_tempfile, BackendMap, DiffLsp,
+};
 
-const LOG_FILE_PATH: &str = "~/.diff-lsp.log";
+// Set from --log-file before the logger starts
+static LOG_FILE: OnceLock<PathBuf> = OnceLock::new();
 
-fn logfile_path() -> PathBuf {
-    // println!("setting logfile path");
-    expanduser(LOG_FILE_PATH).unwrap()
+fn logfile_path() -> &'static Path {
+    LOG_FILE.get().expect("log file is set before logging")
 }
 
 struct FileLogger;
 
 impl Log for FileLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Debug
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &Record) {
@@ -61,81 +72,225 @@ impl Log for FileLogger {
 
 static LOGGER: FileLogger = FileLogger;
 
-pub fn initialize_logger() -> Result<(), SetLoggerError> {
-    log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Info))
+/// `diff-lsp doctor`: prints which backends can be started, for working out
+/// why a language gets no hovers.
+fn doctor(config: Option<&Path>) {
+    let config_path = config.map_or_else(default_config_path, Path::to_path_buf);
+    if config_path.exists() {
+        println!("Config: {}", config_path.display());
+    } else {
+        println!(
+            "Config: {} (not found, using defaults)",
+            config_path.display()
+        );
+    }
+    match LanguageRegistry::load(config) {
+        Ok(languages) => print!("{}", report(&check_backends(&languages))),
+        Err(e) => println!("Failed to load config: {}", e),
+    }
+}
+
+/// `diff-lsp render <file>`: prints the text an editor should show for a
+/// diff, which is the file itself unless it's a saved review.
+fn render(file: &Path) {
+    match read_to_string(file) {
+        Ok(contents) => match ReviewExport::from_json(&contents) {
+            Some(review) => print!("{}", review.render().0),
+            None => print!("{}", contents),
+        },
+        Err(e) => eprintln!("Failed to read {:?}: {}", file, e),
+    }
+}
+
+/// How a proxy starts a daemon with the same settings as itself.
+fn daemon_command(cli: &Cli, address: &DaemonAddress) -> process::Command {
+    let exe = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("diff-lsp"));
+    let mut command = process::Command::new(exe);
+    command
+        .arg("--log-file")
+        .arg(&cli.log_file)
+        .arg("--log-level")
+        .arg(cli.log_level.to_string());
+    if let Some(config) = &cli.config {
+        command.arg("--config").arg(config);
+    }
+    if cli.no_fetch {
+        command.arg("--no-fetch");
+    }
+    command.arg("daemon");
+    if let DaemonAddress::Unix(path) = address {
+        command.arg("--socket").arg(path);
+    }
+    // stdout is the proxy's LSP stream, and the daemon should outlive the
+    // editor which started it
+    command
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .process_group(0);
+    command
+}
+
+pub fn initialize_logger(level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_logger(&LOGGER).map(|()| log::set_max_level(level))
 }
 
 #[tokio::main]
 async fn main() {
-    if let Err(e) = initialize_logger() {
+    let cli = Cli::parse();
+    let log_file = match expanduser(&cli.log_file) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Invalid log file {:?}: {}", cli.log_file, e);
+            return;
+        }
+    };
+    LOG_FILE.get_or_init(|| log_file);
+    if let Err(e) = initialize_logger(cli.log_level) {
         eprintln!("Failed to initialize logger: {}", e);
         return;
     }
-    let (stdin, stdout) = (tokio::io::stdin(), tokio::io::stdout());
-
-    let tempfile_path = match get_most_recent_file("/tmp", "diff_lsp_") {
-        Ok(Some(path)) => path,
-        Ok(None) => {
-            eprintln!("No initialization tempfile found in /tmp/diff_lsp_*");
-            return;
-        }
-        Err(e) => {
-            eprintln!("Error searching for tempfile: {}", e);
+    match &cli.command {
+        Some(Command::Doctor) => return doctor(cli.config.as_deref()),
+        Some(Command::Render { file }) => return render(file),
+        Some(Command::Proxy { address }) => {
+            let address = DaemonAddress::new(address.socket.clone(), address.tcp.clone());
+            if let Err(e) = proxy(&address, Some(daemon_command(&cli, &address))).await {
+                eprintln!("Proxy failed: {}", e);
+            }
             return;
         }
-    };
+        _ => (),
+    }
 
-    info!("Looking at tempfile: {:?}", tempfile_path);
-    let (cwd, worktree, langs) = match read_initialization_params_from_tempfile(&tempfile_path) {
-        Ok(res) => res,
+    let Config {
+        languages,
+        mut fetch,
+        worktrees,
+        hunks,
+    } = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
         Err(e) => {
-            eprintln!(
-                "Failed to read initialization params from {:?}: {}",
-                tempfile_path, e
-            );
+            eprintln!("Failed to load config: {}", e);
             return;
         }
     };
+    if cli.no_fetch {
+        fetch.enabled = false;
+    }
+    if let Some(Command::Daemon { address }) = &cli.command {
+        let address = DaemonAddress::new(address.socket.clone(), address.tcp.clone());
+        let mut daemon = Daemon::new(languages);
+        daemon.fetch = fetch;
+        daemon.worktrees = worktrees;
+        daemon.hunks = hunks;
+        if let Err(e) = daemon.run(&address).await {
+            info!("Daemon stopped: {:?}", e);
+            eprintln!("Daemon failed: {}", e);
+        }
+        return;
+    }
+    if let Some(unknown) = cli
+        .languages
+        .iter()
+        .find(|name| languages.get(name).is_none())
+    {
+        eprintln!("Unknown language {:?}", unknown);
+        return;
+    }
 
-    info!("hurr");
-    fetch_origin_nonblocking(&cwd);
-
-    let mut backend_root = cwd.clone();
-    if let Some(wt) = worktree {
-        let wt_path = std::path::Path::new(&cwd).join(&wt);
-        if wt_path.exists() {
-            info!("Using worktree at {:?}", wt_path);
-            if let Ok(path_str) = wt_path.into_os_string().into_string() {
-                backend_root = path_str;
+    // The tempfile is only read when asked for, so sessions started at the
+    // same time can't pick up each other's.  Everything in it can be sent as
+    // initializationOptions or flags instead.
+    let (mut root, mut worktree, mut langs) = (None, None, vec![]);
+    if let Some(tempfile_path) = &cli.init_file {
+        info!("Looking at tempfile: {:?}", tempfile_path);
+        match read_initialization_params_from_tempfile(tempfile_path, &languages) {
+            Ok((cwd, tempfile_worktree, tempfile_langs)) => {
+                root = Some(cwd);
+                worktree = tempfile_worktree;
+                langs = tempfile_langs;
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to read initialization params from {:?}: {}",
+                    tempfile_path, e
+                );
+                return;
+            }
+        };
+    }
+    if let Some(cli_root) = &cli.root {
+        match expanduser(cli_root) {
+            Ok(path) => root = Some(path.display().to_string()),
+            Err(e) => {
+                eprintln!("Invalid root {:?}: {}", cli_root, e);
+                return;
             }
-        } else {
-            info!(
-                "Worktree {:?} does not exist, falling back to root",
-                wt_path
-            );
         }
     }
+    worktree = cli.worktree.clone().or(worktree);
+    langs.extend(cli.languages.iter().cloned());
 
-    info!("Starting to create backends");
-    let backends = match create_backends_map(langs, &backend_root) {
-        Ok(b) => b,
-        Err(e) => {
-            info!("Errored on starting backends map: {:?}", e);
-            eprintln!(
-                "Failed to create backends for directory {}: {}",
-                backend_root, e
-            );
-            return;
-        }
-    };
-    info!("Done create backends");
-    let (diff_lsp_service, socket) =
-        LspService::new(|client| DiffLsp::new(client, backends, backend_root.to_string()));
+    let mut backend_root = String::new();
+    let mut backends = BackendMap::new();
+    if let Some(root) = root {
+        backend_root = server::backend_root(&root, worktree.as_deref());
+        info!("Starting to create backends");
+        backends = match create_backends_map(langs, &backend_root, &languages) {
+            Ok(b) => b,
+            Err(e) => {
+                info!("Errored on starting backends map: {:?}", e);
+                eprintln!(
+                    "Failed to create backends for directory {}: {}",
+                    backend_root, e
+                );
+                return;
+            }
+        };
+        info!("Done create backends");
+    }
+    let (diff_lsp_service, socket) = LspService::new(|client| {
+        let mut diff_lsp = DiffLsp::new(client, backends, backend_root.to_string(), languages);
+        diff_lsp.fetch = fetch;
+        diff_lsp.worktrees = worktrees;
+        diff_lsp.hunks = hunks;
+        diff_lsp
+    });
 
     info!("Starting server@{:?}", backend_root);
 
-    Server::new(stdin, stdout, socket)
-        .serve(diff_lsp_service)
-        .await;
+    match &cli.listen {
+        Some(address) => {
+            let listener = match TcpListener::bind(address).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("Failed to listen on {}: {}", address, e);
+                    return;
+                }
+            };
+            info!("Waiting for a client on {}", address);
+            let stream = match listener.accept().await {
+                Ok((stream, peer)) => {
+                    info!("Client connected from {}", peer);
+                    stream
+                }
+                Err(e) => {
+                    eprintln!("Failed to accept a client on {}: {}", address, e);
+                    return;
+                }
+            };
+            let (read, write) = tokio::io::split(stream);
+            Server::new(read, write, socket)
+                .serve(diff_lsp_service)
+                .await;
+        }
+        None => {
+            let (stdin, stdout) = (tokio::io::stdin(), tokio::io::stdout());
+            Server::new(stdin, stdout, socket)
+                .serve(diff_lsp_service)
+                .await;
+        }
+    }
     println!("Goodbye world.");
 }
@@ -0,0 +1,65 @@
+use tower_lsp::lsp_types::{
+    GotoDefinitionResponse, HoverProviderCapability, Location, OneOf, ServerCapabilities,
+    TypeDefinitionProviderCapability,
+};
+
+use crate::client::supports_method;
+
+/// Combines the locations returned by several backends, keeping the order
+/// they were returned in and dropping duplicates.
+pub fn merge_locations(responses: Vec<Vec<Location>>) -> Vec<Location> {
+    let mut merged: Vec<Location> = vec![];
+    for location in responses.into_iter().flatten() {
+        if !merged.contains(&location) {
+            merged.push(location);
+        }
+    }
+    merged
+}
+
+fn goto_locations(response: GotoDefinitionResponse) -> Vec<Location> {
+    match response {
+        GotoDefinitionResponse::Scalar(location) => vec![location],
+        GotoDefinitionResponse::Array(locations) => locations,
+        GotoDefinitionResponse::Link(links) => links
+            .into_iter()
+            .map(|link| Location {
+                uri: link.target_uri,
+                range: link.target_selection_range,
+            })
+            .collect(),
+    }
+}
+
+/// Combines definition responses.  A single response is passed on as is,
+/// several are flattened into one list of locations.  Also used for type
+/// definitions, which share the response type.
+pub fn merge_goto_responses(
+    mut responses: Vec<GotoDefinitionResponse>,
+) -> Option<GotoDefinitionResponse> {
+    match responses.len() {
+        0 => None,
+        1 => responses.pop(),
+        _ => Some(GotoDefinitionResponse::Array(merge_locations(
+            responses.into_iter().map(goto_locations).collect(),
+        ))),
+    }
+}
+
+/// The requests diff-lsp can answer given what its backends support: a
+/// request is advertised when any backend handles it.
+pub fn merge_capabilities(backends: &[ServerCapabilities]) -> ServerCapabilities {
+    let any = |method: &str| {
+        backends
+            .iter()
+            .any(|capabilities| supports_method(capabilities, method))
+    };
+    ServerCapabilities {
+        hover_provider: any("textDocument/hover").then_some(HoverProviderCapability::Simple(true)),
+        definition_provider: any("textDocument/definition").then_some(OneOf::Left(true)),
+        references_provider: any("textDocument/references").then_some(OneOf::Left(true)),
+        type_definition_provider: any("textDocument/typeDefinition")
+            .then_some(TypeDefinitionProviderCapability::Simple(true)),
+        ..ServerCapabilities::default()
+    }
+}
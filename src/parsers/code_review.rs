@@ -5,6 +5,7 @@ use chrono::Utc;
 use log::info;
 use regex::Regex;
 
+use crate::parsers::review::{parse_comment_box, parse_review_block, ReviewThread};
 use crate::parsers::utils::*;
 
 /// CodeReviewDiffs are the output of the code-review emacs package
@@ -16,6 +17,7 @@ pub struct CodeReviewDiff {
     // pub hunks: Vec<Hunk>,
     pub filenames: Vec<String>, // relative path, i.e. /src/client.rs
     lines_map: HashMap<InputLineNumber, (String, DiffLine)>,
+    pub threads: Vec<ReviewThread>,
     total_lines: usize,
     src: String,
 }
@@ -28,11 +30,29 @@ impl Parsable for CodeReviewDiff {
                 filenames: cr_diff.filenames,
                 lines_map: cr_diff.lines_map,
                 parsed_at: Utc::now(),
+                vcs: Self::vcs(),
+                threads: cr_diff.threads,
+                offsets: HashMap::new(),
                 total_lines: cr_diff.total_lines,
             });
         }
         None
     }
+
+    fn detect(source: &str) -> u8 {
+        if source
+            .lines()
+            .any(|line| line.trim_start().starts_with("┌─ REVIEW COMMENT"))
+        {
+            60
+        } else if source.lines().any(is_file_header) && source.lines().any(|l| l.starts_with("@@"))
+        {
+            // Plain diffs are close enough to what code-review renders
+            20
+        } else {
+            0
+        }
+    }
 }
 
 impl CodeReviewDiff {
@@ -45,6 +65,9 @@ impl CodeReviewDiff {
         let mut start_new: u16 = 0; // TODO new variable name
         let mut at_source_line: u16 = 0;
         let mut in_review = false;
+        let mut review_lines: Vec<&str> = vec![];
+        // the last diff line, which any comments below it are on
+        let mut last_line: Option<(InputLineNumber, SourceLineNumber)> = None;
         let mut line_num;
 
         for (i, line) in source.lines().enumerate() {
@@ -80,6 +103,7 @@ impl CodeReviewDiff {
                 info!("Current filename when parsing: {:?}", current_filename);
                 diff.filenames.push(current_filename.to_string());
                 building_hunk = false;
+                last_line = None;
             }
             if line.starts_with("@@") && !building_hunk {
                 building_hunk = true;
@@ -105,6 +129,7 @@ impl CodeReviewDiff {
                 if line.starts_with("Reviewed by") || line.starts_with("Comment by") {
                     info!("D: ({:?}) Review Start (old format): {}", line_num, line);
                     in_review = true;
+                    review_lines = vec![line];
                     continue;
                 }
 
@@ -116,6 +141,7 @@ impl CodeReviewDiff {
                         line_num, line
                     );
                     in_review = true;
+                    review_lines = vec![line];
                     continue;
                 }
             }
@@ -125,6 +151,8 @@ impl CodeReviewDiff {
                 if line.starts_with("-------") {
                     info!("D: ({:?}) Review End (old format): {}", line_num, line);
                     in_review = false;
+                    let thread = parse_review_block(&review_lines, current_filename);
+                    diff.push_thread(thread, last_line);
                     continue;
                 }
 
@@ -133,11 +161,15 @@ impl CodeReviewDiff {
                 if line.trim_start().starts_with("└") {
                     info!("D: ({:?}) Box Comment End (new format): {}", line_num, line);
                     in_review = false;
+                    review_lines.push(line);
+                    let thread = parse_comment_box(&review_lines, current_filename);
+                    diff.push_thread(thread, last_line);
                     continue;
                 }
 
-                // Skip all lines within comments (both formats)
+                // Lines within comments (both formats) are kept for the thread
                 info!("D: ({:?}) Review Line: {}", line_num, line);
+                review_lines.push(line);
                 continue;
             }
 
@@ -150,10 +182,12 @@ impl CodeReviewDiff {
                 };
 
                 // the  line_num is because line_num is 0 index, but file lines are 1 index.
+                let input_line = InputLineNumber::new((line_num).try_into().unwrap());
                 diff.lines_map.insert(
-                    InputLineNumber::new((line_num).try_into().unwrap()),
+                    input_line,
                     (current_filename.to_string(), diff_line.clone()),
                 );
+                last_line = Some((input_line, diff_line.source_line_number));
 
                 info!(
                     "C: ({:?}) Adding line @ {:?} `{}`",
@@ -170,4 +204,17 @@ impl CodeReviewDiff {
         diff.total_lines = source.lines().count();
         Some(diff)
     }
+
+    /// Adds a thread read from a comment, on the diff line drawn above it.
+    fn push_thread(
+        &mut self,
+        mut thread: ReviewThread,
+        line: Option<(InputLineNumber, SourceLineNumber)>,
+    ) {
+        if let Some((input_line, source_line)) = line {
+            thread.input_line = Some(input_line);
+            thread.source_line = Some(source_line);
+        }
+        self.threads.push(thread);
+    }
 }
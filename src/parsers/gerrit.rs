@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::parsers::review::*;
+use crate::parsers::utils::*;
+
+/// A Gerrit change saved from its REST API.  `files` holds the result of
+/// `GET /changes/{id}/revisions/{rev}/files/{file}/diff` for each file and
+/// `comments` the result of `GET /changes/{id}/revisions/{rev}/comments`.
+/// `change` is the optional `GET /changes/{id}` object, used for headers.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GerritChange {
+    pub change: Option<GerritChangeInfo>,
+    pub files: BTreeMap<String, GerritDiffInfo>,
+    #[serde(default)]
+    pub comments: BTreeMap<String, Vec<GerritCommentInfo>>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GerritChangeInfo {
+    #[serde(rename = "_number")]
+    pub number: Option<u64>,
+    pub project: Option<String>,
+    pub branch: Option<String>,
+    pub subject: Option<String>,
+    pub status: Option<String>,
+    pub owner: Option<GerritAccount>,
+    pub current_revision: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GerritAccount {
+    pub name: Option<String>,
+    pub username: Option<String>,
+}
+
+impl GerritAccount {
+    fn display_name(&self) -> String {
+        self.username
+            .clone()
+            .or(self.name.clone())
+            .unwrap_or("unknown".to_string())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GerritDiffInfo {
+    pub change_type: Option<String>,
+    pub content: Vec<GerritDiffContent>,
+}
+
+/// One chunk of a Gerrit diff: lines only in the old file (`a`), only in the
+/// new file (`b`), in both (`ab`), or a number of unchanged lines left out.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GerritDiffContent {
+    #[serde(default)]
+    pub a: Vec<String>,
+    #[serde(default)]
+    pub b: Vec<String>,
+    #[serde(default)]
+    pub ab: Vec<String>,
+    pub skip: Option<u16>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GerritCommentInfo {
+    pub id: String,
+    pub line: Option<u16>,
+    // PARENT for comments on the base, missing for the patchset
+    pub side: Option<String>,
+    pub message: String,
+    pub author: Option<GerritAccount>,
+    pub updated: Option<String>,
+    pub in_reply_to: Option<String>,
+}
+
+impl GerritCommentInfo {
+    fn to_comment(&self) -> ReviewComment {
+        ReviewComment {
+            id: Some(self.id.clone()),
+            author: self
+                .author
+                .as_ref()
+                .map_or("unknown".to_string(), |a| a.display_name()),
+            // Gerrit timestamps are `2025-05-03 01:55:55.000000000`
+            timestamp: self
+                .updated
+                .as_ref()
+                .map(|updated| updated.split('.').next().unwrap().to_string()),
+            body: self.message.clone(),
+        }
+    }
+}
+
+/// Writes out `hunk`, which covers the lines `old.0..old.1` of the old file
+/// and `new.0..new.1` of the new one.
+fn flush_hunk(patch: &mut String, hunk: &mut Vec<String>, old: (u16, u16), new: (u16, u16)) {
+    if hunk.is_empty() {
+        return;
+    }
+    // Like git, an empty side starts at the line before
+    let start = |(start, end): (u16, u16)| if start == end { start - 1 } else { start };
+    patch.push_str(&format!(
+        "@@ -{},{} +{},{} @@\n",
+        start(old),
+        old.1 - old.0,
+        start(new),
+        new.1 - new.0
+    ));
+    patch.push_str(&hunk.join("\n"));
+    patch.push('\n');
+    hunk.clear();
+}
+
+impl Parsable for GerritChange {
+    fn parse(source: &str) -> Option<ParsedDiff> {
+        GerritChange::from_json(source)?.to_export().parse()
+    }
+
+    fn detect(source: &str) -> u8 {
+        if GerritChange::from_json(source).is_some() {
+            90
+        } else {
+            0
+        }
+    }
+}
+
+impl GerritChange {
+    pub fn from_json(source: &str) -> Option<Self> {
+        // Gerrit prefixes its responses to stop them being run as javascript
+        let source = source.trim_start();
+        let source = source.strip_prefix(")]}'").unwrap_or(source);
+        serde_json::from_str::<GerritChange>(source).ok()
+    }
+
+    /// Gerrit has no hunks, only runs of changed and unchanged lines.  This
+    /// turns them into a unified diff, starting a new hunk after each skip.
+    pub fn to_patch(diff: &GerritDiffInfo) -> String {
+        let mut patch = String::new();
+        let mut hunk: Vec<String> = vec![];
+        let (mut old_line, mut new_line): (u16, u16) = (1, 1);
+        let (mut old_start, mut new_start) = (old_line, new_line);
+
+        for content in diff.content.iter() {
+            if let Some(skip) = content.skip {
+                flush_hunk(
+                    &mut patch,
+                    &mut hunk,
+                    (old_start, old_line),
+                    (new_start, new_line),
+                );
+                old_line += skip;
+                new_line += skip;
+                old_start = old_line;
+                new_start = new_line;
+                continue;
+            }
+            for line in content.ab.iter() {
+                hunk.push(format!(" {}", line));
+                old_line += 1;
+                new_line += 1;
+            }
+            for line in content.a.iter() {
+                hunk.push(format!("-{}", line));
+                old_line += 1;
+            }
+            for line in content.b.iter() {
+                hunk.push(format!("+{}", line));
+                new_line += 1;
+            }
+        }
+        flush_hunk(
+            &mut patch,
+            &mut hunk,
+            (old_start, old_line),
+            (new_start, new_line),
+        );
+        patch
+    }
+
+    /// Gerrit replies point at the comment they answer, which may itself be a
+    /// reply.  Each is grouped under the comment at the top of its chain.
+    pub fn threads(&self) -> Vec<AnchoredThread> {
+        let mut threads = vec![];
+        for (path, comments) in self.comments.iter() {
+            let root_of = |comment: &GerritCommentInfo| {
+                let mut current = comment;
+                while let Some(parent) = current
+                    .in_reply_to
+                    .as_ref()
+                    .and_then(|id| comments.iter().find(|c| &c.id == id))
+                {
+                    current = parent;
+                }
+                current.id.clone()
+            };
+
+            for root in comments.iter().filter(|c| c.in_reply_to.is_none()) {
+                threads.push(AnchoredThread {
+                    thread: ReviewThread {
+                        file_name: path.clone(),
+                        comment: root.to_comment(),
+                        replies: comments
+                            .iter()
+                            .filter(|c| c.in_reply_to.is_some() && root_of(c) == root.id)
+                            .map(|c| c.to_comment())
+                            .collect(),
+                        ..Default::default()
+                    },
+                    side: match root.side.as_deref() {
+                        Some("PARENT") => Side::Old,
+                        _ => Side::New,
+                    },
+                    line: root.line,
+                });
+            }
+        }
+        threads
+    }
+
+    pub fn to_export(&self) -> ReviewExport {
+        let mut headers = vec![];
+        if let Some(change) = &self.change {
+            if let (Some(project), Some(number)) = (&change.project, change.number) {
+                headers.push(("Project".to_string(), format!("{} #{}", project, number)));
+                headers.push(("Buffer".to_string(), format!("Change {}", number)));
+            }
+            if let Some(subject) = &change.subject {
+                headers.push(("Title".to_string(), subject.clone()));
+            }
+            if let Some(owner) = &change.owner {
+                headers.push(("Author".to_string(), owner.display_name()));
+            }
+            if let Some(status) = &change.status {
+                headers.push(("State".to_string(), status.clone()));
+            }
+            if let Some(revision) = &change.current_revision {
+                headers.push(("Head".to_string(), revision.clone()));
+            }
+        }
+
+        ReviewExport {
+            headers,
+            files: self
+                .files
+                .iter()
+                // Skip Gerrit's magic files like /COMMIT_MSG
+                .filter(|(path, _)| !path.starts_with('/'))
+                .map(|(path, diff)| ReviewFile {
+                    filename: path.clone(),
+                    status: match diff.change_type.as_deref() {
+                        Some("ADDED") => FileStatus::Added,
+                        Some("DELETED") => FileStatus::Removed,
+                        _ => FileStatus::Modified,
+                    },
+                    patch: Some(GerritChange::to_patch(diff)),
+                })
+                .collect(),
+            threads: self.threads(),
+        }
+    }
+
+    pub fn render(&self) -> (String, Vec<ReviewThread>) {
+        self.to_export().render()
+    }
+}
@@ -0,0 +1,189 @@
+use serde::Deserialize;
+
+use crate::parsers::review::*;
+use crate::parsers::utils::*;
+
+/// A pull request saved from the GitHub REST API.  This is the pull request
+/// object with the results of `GET /pulls/{n}/files` added as `files` and
+/// `GET /pulls/{n}/comments` as `comments`.  A bare array of files is accepted
+/// too.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GithubPullRequest {
+    pub number: Option<u64>,
+    pub title: Option<String>,
+    pub state: Option<String>,
+    pub user: Option<GithubUser>,
+    pub head: Option<GithubRef>,
+    pub base: Option<GithubRef>,
+    pub files: Vec<GithubFile>,
+    #[serde(default)]
+    pub comments: Vec<GithubReviewComment>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GithubUser {
+    pub login: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GithubRepo {
+    pub full_name: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GithubRef {
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+    pub sha: String,
+    pub repo: Option<GithubRepo>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GithubFile {
+    pub filename: String,
+    pub status: String,
+    // Binary and very large files have no patch
+    pub patch: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GithubReviewComment {
+    pub id: u64,
+    pub path: String,
+    // null when the comment is outdated
+    pub line: Option<u16>,
+    pub side: Option<String>,
+    pub body: String,
+    pub user: Option<GithubUser>,
+    pub created_at: Option<String>,
+    pub in_reply_to_id: Option<u64>,
+}
+
+impl GithubReviewComment {
+    fn to_comment(&self) -> ReviewComment {
+        ReviewComment {
+            id: Some(self.id.to_string()),
+            author: self
+                .user
+                .as_ref()
+                .map_or("unknown".to_string(), |u| u.login.clone()),
+            timestamp: self.created_at.as_deref().map(format_timestamp),
+            body: self.body.clone(),
+        }
+    }
+}
+
+impl Parsable for GithubPullRequest {
+    fn parse(source: &str) -> Option<ParsedDiff> {
+        GithubPullRequest::from_json(source)?.to_export().parse()
+    }
+
+    fn detect(source: &str) -> u8 {
+        let trimmed = source.trim_start();
+        if (trimmed.starts_with('{') || trimmed.starts_with('['))
+            && GithubPullRequest::from_json(source).is_some()
+        {
+            90
+        } else {
+            0
+        }
+    }
+}
+
+impl GithubPullRequest {
+    pub fn from_json(source: &str) -> Option<Self> {
+        if let Ok(pr) = serde_json::from_str::<GithubPullRequest>(source) {
+            return Some(pr);
+        }
+        serde_json::from_str::<Vec<GithubFile>>(source)
+            .ok()
+            .map(|files| GithubPullRequest {
+                files,
+                ..Default::default()
+            })
+    }
+
+    /// Groups the flat comment list into threads.  GitHub points every reply
+    /// at the first comment of its thread.
+    pub fn threads(&self) -> Vec<AnchoredThread> {
+        let mut threads: Vec<(u64, AnchoredThread)> = vec![];
+        for comment in self.comments.iter() {
+            match comment.in_reply_to_id {
+                Some(parent) => {
+                    if let Some((_, anchored)) = threads.iter_mut().find(|(id, _)| *id == parent) {
+                        anchored.thread.replies.push(comment.to_comment());
+                    }
+                }
+                None => threads.push((
+                    comment.id,
+                    AnchoredThread {
+                        thread: ReviewThread {
+                            file_name: comment.path.clone(),
+                            comment: comment.to_comment(),
+                            ..Default::default()
+                        },
+                        side: match comment.side.as_deref() {
+                            Some("LEFT") => Side::Old,
+                            _ => Side::New,
+                        },
+                        line: comment.line,
+                    },
+                )),
+            }
+        }
+        threads.into_iter().map(|(_, thread)| thread).collect()
+    }
+
+    pub fn to_export(&self) -> ReviewExport {
+        let mut headers = vec![];
+        if let Some(number) = self.number {
+            if let Some(repo) = self.base.as_ref().and_then(|base| base.repo.as_ref()) {
+                headers.push((
+                    "Project".to_string(),
+                    format!("{} #{}", repo.full_name, number),
+                ));
+            }
+            headers.push(("Buffer".to_string(), format!("PR #{}", number)));
+        }
+        if let Some(title) = &self.title {
+            headers.push(("Title".to_string(), title.clone()));
+        }
+        if let Some(user) = &self.user {
+            headers.push(("Author".to_string(), format!("@{}", user.login)));
+        }
+        if let Some(state) = &self.state {
+            headers.push(("State".to_string(), state.clone()));
+        }
+        if let (Some(base), Some(head)) = (&self.base, &self.head) {
+            headers.push((
+                "Refs".to_string(),
+                format!("{} ... {}", base.ref_name, head.ref_name),
+            ));
+        }
+        if let Some(head) = &self.head {
+            headers.push(("Head".to_string(), head.sha.clone()));
+        }
+
+        ReviewExport {
+            headers,
+            files: self
+                .files
+                .iter()
+                .map(|file| ReviewFile {
+                    filename: file.filename.clone(),
+                    status: match file.status.as_str() {
+                        "added" => FileStatus::Added,
+                        "removed" => FileStatus::Removed,
+                        _ => FileStatus::Modified,
+                    },
+                    patch: file.patch.clone(),
+                })
+                .collect(),
+            threads: self.threads(),
+        }
+    }
+
+    pub fn render(&self) -> (String, Vec<ReviewThread>) {
+        self.to_export().render()
+    }
+}
@@ -0,0 +1,222 @@
+use serde::Deserialize;
+
+use crate::parsers::review::*;
+use crate::parsers::utils::*;
+
+/// A merge request saved from the GitLab REST API.  This is the result of
+/// `GET /merge_requests/{iid}/changes`, which includes the `changes`, with the
+/// result of `GET /merge_requests/{iid}/discussions` added as `discussions`.
+/// `diffs` from `GET /merge_requests/{iid}/diffs` is accepted in place of
+/// `changes`, as is a bare array of diffs.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GitlabMergeRequest {
+    pub iid: u64,
+    pub title: Option<String>,
+    pub state: Option<String>,
+    pub author: Option<GitlabUser>,
+    pub source_branch: Option<String>,
+    pub target_branch: Option<String>,
+    pub sha: Option<String>,
+    pub references: Option<GitlabReferences>,
+    #[serde(default)]
+    pub changes: Vec<GitlabDiff>,
+    #[serde(default)]
+    pub diffs: Vec<GitlabDiff>,
+    #[serde(default)]
+    pub discussions: Vec<GitlabDiscussion>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GitlabUser {
+    pub username: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GitlabReferences {
+    // `group/project!12`
+    pub full: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GitlabDiff {
+    pub old_path: String,
+    pub new_path: String,
+    #[serde(default)]
+    pub new_file: bool,
+    #[serde(default)]
+    pub deleted_file: bool,
+    // Starts at the first `@@`, empty for binary files
+    pub diff: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GitlabDiscussion {
+    pub id: String,
+    pub notes: Vec<GitlabNote>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GitlabNote {
+    pub id: u64,
+    pub body: String,
+    pub author: Option<GitlabUser>,
+    pub created_at: Option<String>,
+    // Notes GitLab writes itself, such as "added 1 commit"
+    #[serde(default)]
+    pub system: bool,
+    // Only set for notes on the diff
+    pub position: Option<GitlabPosition>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GitlabPosition {
+    pub new_path: Option<String>,
+    pub old_path: Option<String>,
+    pub new_line: Option<u16>,
+    pub old_line: Option<u16>,
+}
+
+impl GitlabNote {
+    fn to_comment(&self) -> ReviewComment {
+        ReviewComment {
+            id: Some(self.id.to_string()),
+            author: self
+                .author
+                .as_ref()
+                .map_or("unknown".to_string(), |a| a.username.clone()),
+            timestamp: self.created_at.as_deref().map(format_timestamp),
+            body: self.body.clone(),
+        }
+    }
+}
+
+impl Parsable for GitlabMergeRequest {
+    fn parse(source: &str) -> Option<ParsedDiff> {
+        GitlabMergeRequest::from_json(source)?.to_export().parse()
+    }
+
+    fn detect(source: &str) -> u8 {
+        if GitlabMergeRequest::from_json(source).is_some() {
+            90
+        } else {
+            0
+        }
+    }
+}
+
+impl GitlabMergeRequest {
+    pub fn from_json(source: &str) -> Option<Self> {
+        if let Ok(mr) = serde_json::from_str::<GitlabMergeRequest>(source) {
+            return Some(mr);
+        }
+        serde_json::from_str::<Vec<GitlabDiff>>(source)
+            .ok()
+            .map(|diffs| GitlabMergeRequest {
+                diffs,
+                ..Default::default()
+            })
+    }
+
+    /// The changed files, from whichever of `changes` or `diffs` was saved.
+    pub fn files(&self) -> &[GitlabDiff] {
+        if self.changes.is_empty() {
+            &self.diffs
+        } else {
+            &self.changes
+        }
+    }
+
+    /// Each discussion on the diff is a thread, its first note the comment
+    /// and the rest replies.  Discussions on the merge request itself have no
+    /// line to go under and are left out.
+    pub fn threads(&self) -> Vec<AnchoredThread> {
+        let mut threads = vec![];
+        for discussion in self.discussions.iter() {
+            let (first, rest) = match discussion.notes.split_first() {
+                Some(notes) => notes,
+                None => continue,
+            };
+            let position = match &first.position {
+                Some(position) if !first.system => position,
+                _ => continue,
+            };
+            // Comments on removed lines only have an old_line
+            let (side, line) = match position.new_line {
+                Some(line) => (Side::New, Some(line)),
+                None => (Side::Old, position.old_line),
+            };
+            let file_name = match side {
+                Side::New => position.new_path.clone(),
+                Side::Old => position.old_path.clone(),
+            };
+            threads.push(AnchoredThread {
+                thread: ReviewThread {
+                    file_name: file_name.unwrap_or_default(),
+                    comment: first.to_comment(),
+                    replies: rest
+                        .iter()
+                        .filter(|note| !note.system)
+                        .map(|note| note.to_comment())
+                        .collect(),
+                    ..Default::default()
+                },
+                side,
+                line,
+            });
+        }
+        threads
+    }
+
+    pub fn to_export(&self) -> ReviewExport {
+        let mut headers = vec![];
+        if self.iid != 0 {
+            if let Some(references) = &self.references {
+                headers.push(("Project".to_string(), references.full.clone()));
+            }
+            headers.push(("Buffer".to_string(), format!("MR !{}", self.iid)));
+        }
+        if let Some(title) = &self.title {
+            headers.push(("Title".to_string(), title.clone()));
+        }
+        if let Some(author) = &self.author {
+            headers.push(("Author".to_string(), format!("@{}", author.username)));
+        }
+        if let Some(state) = &self.state {
+            headers.push(("State".to_string(), state.clone()));
+        }
+        if let (Some(target), Some(source)) = (&self.target_branch, &self.source_branch) {
+            headers.push(("Refs".to_string(), format!("{} ... {}", target, source)));
+        }
+        if let Some(sha) = &self.sha {
+            headers.push(("Head".to_string(), sha.clone()));
+        }
+
+        ReviewExport {
+            headers,
+            files: self
+                .files()
+                .iter()
+                .map(|diff| ReviewFile {
+                    filename: if diff.deleted_file {
+                        diff.old_path.clone()
+                    } else {
+                        diff.new_path.clone()
+                    },
+                    status: if diff.new_file {
+                        FileStatus::Added
+                    } else if diff.deleted_file {
+                        FileStatus::Removed
+                    } else {
+                        FileStatus::Modified
+                    },
+                    patch: (!diff.diff.is_empty()).then(|| diff.diff.clone()),
+                })
+                .collect(),
+            threads: self.threads(),
+        }
+    }
+
+    pub fn render(&self) -> (String, Vec<ReviewThread>) {
+        self.to_export().render()
+    }
+}
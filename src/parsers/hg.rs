@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use log::info;
+use regex::Regex;
+
+use crate::parsers::utils::*;
+use crate::vcs::Vcs;
+
+/// HgDiffs are the output of `hg diff`, both the default unified format with
+/// `diff -r <rev> <file>` headers and the extended `--git` format.  `hg export`
+/// patches are handled too.
+#[allow(dead_code)]
+#[derive(Default, Debug, Clone)]
+pub struct HgDiff {
+    pub headers: HashMap<DiffHeader, String>,
+    pub filenames: Vec<String>, // relative path, i.e. /src/client.rs
+    lines_map: HashMap<InputLineNumber, (String, DiffLine)>,
+    total_lines: usize,
+    src: String,
+}
+
+/// SaplingDiffs are the output of `sl diff`, which shares its format with hg.
+#[derive(Default, Debug, Clone)]
+pub struct SaplingDiff;
+
+impl Parsable for HgDiff {
+    fn parse(source: &str) -> Option<ParsedDiff> {
+        HgDiff::self_parse(source).map(|hg_diff| hg_diff.into_parsed(Self::vcs()))
+    }
+
+    fn detect(source: &str) -> u8 {
+        if source.starts_with("# HG changeset patch") {
+            90
+        } else if source.lines().any(|line| hg_file_header().is_match(line)) {
+            70
+        } else {
+            0
+        }
+    }
+
+    fn vcs() -> Vcs {
+        Vcs::Mercurial
+    }
+}
+
+impl Parsable for SaplingDiff {
+    fn parse(source: &str) -> Option<ParsedDiff> {
+        HgDiff::self_parse(source).map(|hg_diff| hg_diff.into_parsed(Self::vcs()))
+    }
+
+    fn detect(_source: &str) -> u8 {
+        // sl output can't be told apart from hg's, so it needs a Type header
+        0
+    }
+
+    fn vcs() -> Vcs {
+        Vcs::Sapling
+    }
+}
+
+/// `diff -r 1a2b3c4d src/lib.rs`, or `diff -r 1a2b3c4d -r 5e6f7a8b src/lib.rs`
+/// when comparing two revisions.
+fn hg_file_header() -> Regex {
+    Regex::new(r"^diff -r (\w+)(?: -r (\w+))? (.+)$").unwrap()
+}
+
+impl HgDiff {
+    fn into_parsed(self, vcs: Vcs) -> ParsedDiff {
+        ParsedDiff {
+            headers: self.headers,
+            filenames: self.filenames,
+            lines_map: self.lines_map,
+            parsed_at: Utc::now(),
+            total_lines: self.total_lines,
+            vcs,
+            threads: vec![],
+            offsets: HashMap::new(),
+        }
+    }
+
+    fn self_parse(source: &str) -> Option<Self> {
+        let mut diff = HgDiff {
+            headers: parse_headers(source),
+            ..Default::default()
+        };
+
+        let header_line = Regex::new(r"(\w+):\s+(.+)").unwrap();
+        let file_header = hg_file_header();
+        // `# Node ID 5e6f7a8b...` and `# Parent  1a2b3c4d...` from `hg export`
+        let export_header = Regex::new(r"^# (Node ID|Parent)\s+(\w+)").unwrap();
+
+        let mut found_headers = false;
+        let mut current_filename = String::new();
+        let mut building_hunk = false;
+        let mut start_new: u16 = 0;
+        let mut at_source_line: u16 = 0;
+
+        for (i, line) in source.lines().enumerate() {
+            let line_num = i + 1;
+            if !found_headers {
+                if header_line.is_match(line) {
+                    continue;
+                }
+                found_headers = true;
+            }
+
+            if let Some(caps) = export_header.captures(line) {
+                let header = if &caps[1] == "Node ID" {
+                    DiffHeader::CommitId
+                } else {
+                    DiffHeader::ParentChangeId
+                };
+                diff.headers.insert(header, caps[2].to_string());
+                continue;
+            }
+            if let Some(caps) = file_header.captures(line) {
+                // The revision the working copy is compared against
+                diff.headers
+                    .entry(DiffHeader::ParentChangeId)
+                    .or_insert_with(|| caps[1].to_string());
+                if let Some(to_rev) = caps.get(2) {
+                    diff.headers
+                        .entry(DiffHeader::CommitId)
+                        .or_insert_with(|| to_rev.as_str().to_string());
+                }
+                current_filename = caps[3].to_string();
+                info!("Current filename when parsing: {:?}", current_filename);
+                diff.filenames.push(current_filename.clone());
+                building_hunk = false;
+                continue;
+            }
+            if line.starts_with("diff --git ") {
+                let last = line.split_whitespace().last().unwrap();
+                current_filename = last.strip_prefix("b/").unwrap_or(last).to_string();
+                info!("Current filename when parsing: {:?}", current_filename);
+                diff.filenames.push(current_filename.clone());
+                building_hunk = false;
+                continue;
+            }
+            if line.starts_with("@@") {
+                info!("({:?}) Parsing Header `{}`", line_num, line);
+                if let Some((_, _, new_start, _)) = parse_header(line) {
+                    building_hunk = true;
+                    start_new = new_start;
+                    at_source_line = 0;
+                }
+                continue;
+            }
+
+            if building_hunk {
+                let line_type = LineType::from_line(line);
+                diff.lines_map.insert(
+                    InputLineNumber::new(line_num.try_into().unwrap()),
+                    (
+                        current_filename.clone(),
+                        DiffLine {
+                            line_type,
+                            line: line.to_string(),
+                            source_line_number: SourceLineNumber(start_new + at_source_line),
+                        },
+                    ),
+                );
+                if matches!(line_type, LineType::Added | LineType::Unmodified) {
+                    at_source_line += 1;
+                }
+            }
+        }
+        diff.total_lines = source.lines().count();
+        Some(diff)
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use chrono::Utc;
+use log::info;
+use regex::Regex;
+
+use crate::parsers::utils::*;
+use crate::vcs::Vcs;
+
+/// JjDiffs are the output of `jj diff`, either in its default color-words
+/// format (with colors disabled) or with `--git`.
+#[allow(dead_code)]
+#[derive(Default, Debug, Clone)]
+pub struct JjDiff {
+    pub headers: HashMap<DiffHeader, String>,
+    pub filenames: Vec<String>, // relative path, i.e. /src/client.rs
+    lines_map: HashMap<InputLineNumber, (String, DiffLine)>,
+    total_lines: usize,
+    src: String,
+}
+
+impl Parsable for JjDiff {
+    fn parse(source: &str) -> Option<ParsedDiff> {
+        if let Some(jj_diff) = JjDiff::self_parse(source) {
+            return Some(ParsedDiff {
+                headers: jj_diff.headers,
+                filenames: jj_diff.filenames,
+                lines_map: jj_diff.lines_map,
+                parsed_at: Utc::now(),
+                total_lines: jj_diff.total_lines,
+                vcs: Self::vcs(),
+                threads: vec![],
+                offsets: HashMap::new(),
+            });
+        }
+        None
+    }
+
+    fn detect(source: &str) -> u8 {
+        let headers = parse_headers(source);
+        let file_header = color_words_file_header();
+        let color_words = source.lines().any(|line| file_header.is_match(line));
+        // --git output looks like any other git diff, so rely on the change id header
+        let git_with_change_id = headers.contains_key(&DiffHeader::ChangeId)
+            && source.lines().any(|line| line.starts_with("diff --git "));
+        if color_words || git_with_change_id {
+            70
+        } else {
+            0
+        }
+    }
+
+    fn vcs() -> Vcs {
+        Vcs::Jujutsu
+    }
+}
+
+/// `Modified regular file src/lib.rs:`
+fn color_words_file_header() -> Regex {
+    Regex::new(r"^(Modified|Added|Removed) (regular file|executable file|symlink) (.+):$").unwrap()
+}
+
+impl JjDiff {
+    fn self_parse(source: &str) -> Option<Self> {
+        let mut diff = JjDiff {
+            headers: parse_headers(source),
+            ..Default::default()
+        };
+
+        let header_line = Regex::new(r"(\w+):\s+(.+)").unwrap();
+        let file_header = color_words_file_header();
+        // `   1    1: fn main() {`, with either line number left blank for removed/added lines
+        let numbered_line = Regex::new(r"^ *(\d+)? +(\d+)?: ?(.*)$").unwrap();
+
+        let mut current_filename = String::new();
+        let mut building_hunk = false;
+        let mut start_new: u16 = 0;
+        let mut at_source_line: u16 = 0;
+        // color-words removed lines point at the next line in the new file
+        let mut next_new: u16 = 1;
+        let mut found_headers = false;
+
+        for (i, line) in source.lines().enumerate() {
+            let line_num = i + 1;
+            if !found_headers {
+                if header_line.is_match(line) {
+                    continue;
+                }
+                found_headers = true;
+            }
+
+            if line.starts_with("diff --git ") {
+                let last = line.split_whitespace().last().unwrap();
+                current_filename = last.strip_prefix("b/").unwrap_or(last).to_string();
+                info!("Current filename when parsing: {:?}", current_filename);
+                diff.filenames.push(current_filename.clone());
+                building_hunk = false;
+                continue;
+            }
+            if let Some(caps) = file_header.captures(line) {
+                current_filename = caps[3].to_string();
+                info!("Current filename when parsing: {:?}", current_filename);
+                diff.filenames.push(current_filename.clone());
+                building_hunk = false;
+                next_new = 1;
+                continue;
+            }
+            if line.starts_with("@@") {
+                info!("({:?}) Parsing Header `{}`", line_num, line);
+                if let Some((_, _, new_start, _)) = parse_header(line) {
+                    building_hunk = true;
+                    start_new = new_start;
+                    at_source_line = 0;
+                }
+                continue;
+            }
+
+            if building_hunk {
+                // git format
+                let line_type = LineType::from_line(line);
+                diff.lines_map.insert(
+                    InputLineNumber::new(line_num.try_into().unwrap()),
+                    (
+                        current_filename.clone(),
+                        DiffLine {
+                            line_type,
+                            line: line.to_string(),
+                            source_line_number: SourceLineNumber(start_new + at_source_line),
+                        },
+                    ),
+                );
+                if matches!(line_type, LineType::Added | LineType::Unmodified) {
+                    at_source_line += 1;
+                }
+                continue;
+            }
+
+            if current_filename.is_empty() {
+                continue;
+            }
+            if let Some(caps) = numbered_line.captures(line) {
+                // color-words format
+                let old = caps.get(1).map(|m| m.as_str().parse::<u16>().unwrap());
+                let new = caps.get(2).map(|m| m.as_str().parse::<u16>().unwrap());
+                let (line_type, source_line) = match (old, new) {
+                    (Some(_), Some(new)) => (LineType::Unmodified, new),
+                    (None, Some(new)) => (LineType::Added, new),
+                    (Some(_), None) => (LineType::Removed, next_new),
+                    (None, None) => continue,
+                };
+                if let Some(new) = new {
+                    next_new = new + 1;
+                }
+                diff.lines_map.insert(
+                    InputLineNumber::new(line_num.try_into().unwrap()),
+                    (
+                        current_filename.clone(),
+                        DiffLine {
+                            line_type,
+                            line: caps[3].to_string(),
+                            source_line_number: SourceLineNumber(source_line),
+                        },
+                    ),
+                );
+            }
+        }
+        diff.total_lines = source.lines().count();
+        Some(diff)
+    }
+}
+
+/// JjStatus is the output of `jj status`.  It has no hunks, only the changed
+/// files and the working copy / parent change ids.
+#[allow(dead_code)]
+#[derive(Default, Debug, Clone)]
+pub struct JjStatus {
+    pub headers: HashMap<DiffHeader, String>,
+    pub filenames: Vec<String>,
+    total_lines: usize,
+    src: String,
+}
+
+impl Parsable for JjStatus {
+    fn parse(source: &str) -> Option<ParsedDiff> {
+        if let Some(status) = JjStatus::self_parse(source) {
+            return Some(ParsedDiff {
+                headers: status.headers,
+                filenames: status.filenames,
+                lines_map: HashMap::new(),
+                parsed_at: Utc::now(),
+                total_lines: status.total_lines,
+                vcs: Self::vcs(),
+                threads: vec![],
+                offsets: HashMap::new(),
+            });
+        }
+        None
+    }
+
+    fn detect(source: &str) -> u8 {
+        let working_copy = working_copy_regex();
+        if source
+            .lines()
+            .any(|line| line.starts_with("Working copy changes:") || working_copy.is_match(line))
+        {
+            80
+        } else {
+            0
+        }
+    }
+
+    fn vcs() -> Vcs {
+        Vcs::Jujutsu
+    }
+}
+
+/// Both `Working copy : kxqpyzrw 6c8a1e4f desc` and the newer `Working copy  (@) : ...`
+fn working_copy_regex() -> Regex {
+    Regex::new(r"^Working copy\s*(\(@\))?\s*:\s+(\w+)\s+(\w+)").unwrap()
+}
+
+/// Renames are shown as `src/{old.rs => new.rs}`, we only care about the new name.
+pub fn renamed_to(path: &str) -> String {
+    let re = Regex::new(r"^(.*)\{.* => (.*)\}(.*)$").unwrap();
+    match re.captures(path) {
+        Some(caps) => format!("{}{}{}", &caps[1], &caps[2], &caps[3]),
+        None => path.to_string(),
+    }
+}
+
+impl JjStatus {
+    fn self_parse(source: &str) -> Option<Self> {
+        let mut status = JjStatus::default();
+        let working_copy = working_copy_regex();
+        let parent = Regex::new(r"^Parent commit\s*(\(@-\))?\s*:\s+(\w+)\s+(\w+)").unwrap();
+        let changed_file = Regex::new(r"^[MADRC] (.+)$").unwrap();
+        let header_line = Regex::new(r"^(\w+):\s+(.+)").unwrap();
+
+        // Changed files are only listed under `Working copy changes:`
+        let mut in_changes = false;
+
+        for line in source.lines() {
+            if line.starts_with("Working copy changes:") {
+                in_changes = true;
+                continue;
+            }
+            if in_changes {
+                if let Some(caps) = changed_file.captures(line) {
+                    let filename = renamed_to(&caps[1]);
+                    info!("Current filename when parsing: {:?}", filename);
+                    status.filenames.push(filename);
+                    continue;
+                }
+                in_changes = false;
+            }
+            if let Some(caps) = working_copy.captures(line) {
+                status
+                    .headers
+                    .insert(DiffHeader::ChangeId, caps[2].to_string());
+                status
+                    .headers
+                    .insert(DiffHeader::CommitId, caps[3].to_string());
+            } else if let Some(caps) = parent.captures(line) {
+                status
+                    .headers
+                    .insert(DiffHeader::ParentChangeId, caps[2].to_string());
+            } else if let Some(caps) = header_line.captures(line) {
+                if let Ok(header) = DiffHeader::from_str(&caps[1]) {
+                    status.headers.insert(header, caps[2].to_string());
+                }
+            }
+        }
+        status.total_lines = source.lines().count();
+        Some(status)
+    }
+}
@@ -24,11 +24,26 @@ impl Parsable for MagitDiff {
                 filenames: magit_diff.filenames,
                 lines_map: magit_diff.lines_map,
                 parsed_at: Utc::now(),
+                vcs: Self::vcs(),
+                threads: vec![],
+                offsets: HashMap::new(),
                 total_lines: 0,
             });
         }
         None
     }
+
+    fn detect(source: &str) -> u8 {
+        // Headerless magit buffers still have their section titles
+        let has_section = source.lines().any(|line| {
+            line.starts_with("Unstaged changes (") || line.starts_with("Staged changes (")
+        });
+        if has_section && source.lines().any(|line| line.starts_with("modified ")) {
+            60
+        } else {
+            0
+        }
+    }
 }
 
 #[allow(dead_code)]
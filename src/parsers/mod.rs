@@ -1,3 +1,10 @@
 pub mod code_review;
+pub mod gerrit;
+pub mod github;
+pub mod gitlab;
+pub mod hg;
+pub mod jj;
 pub mod magit;
+pub mod registry;
+pub mod review;
 pub mod utils;
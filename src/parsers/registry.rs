@@ -0,0 +1,158 @@
+use log::info;
+
+use crate::parsers::code_review::CodeReviewDiff;
+use crate::parsers::gerrit::GerritChange;
+use crate::parsers::github::GithubPullRequest;
+use crate::parsers::gitlab::GitlabMergeRequest;
+use crate::parsers::hg::{HgDiff, SaplingDiff};
+use crate::parsers::jj::{JjDiff, JjStatus};
+use crate::parsers::magit::MagitDiff;
+use crate::parsers::utils::*;
+use crate::vcs::Vcs;
+
+/// A single diff format the registry knows how to detect and parse.
+#[derive(Debug, Clone, Copy)]
+pub struct ParserEntry {
+    /// Canonical name of the format, matched against the `Format:` header and
+    /// the `format` initialization option.
+    pub format: &'static str,
+    /// Other names the format answers to, such as older `Type:` header values.
+    pub aliases: &'static [&'static str],
+    pub vcs: Vcs,
+    detect: fn(&str) -> u8,
+    parse: fn(&str) -> Option<ParsedDiff>,
+}
+
+impl ParserEntry {
+    pub fn new<P: Parsable>(format: &'static str, aliases: &'static [&'static str]) -> Self {
+        ParserEntry {
+            format,
+            aliases,
+            vcs: P::vcs(),
+            detect: P::detect,
+            parse: P::parse,
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.format == name || self.aliases.contains(&name)
+    }
+
+    pub fn detect(&self, source: &str) -> u8 {
+        (self.detect)(source)
+    }
+
+    pub fn parse(&self, source: &str) -> Option<ParsedDiff> {
+        (self.parse)(source)
+    }
+}
+
+/// An ordered list of parsers.  When detecting, the most confident parser wins
+/// and ties go to whichever was registered first.
+#[derive(Debug, Clone)]
+pub struct ParserRegistry {
+    entries: Vec<ParserEntry>,
+}
+
+impl Default for ParserRegistry {
+    fn default() -> Self {
+        let mut registry = ParserRegistry::new();
+        registry.register(ParserEntry::new::<MagitDiff>("magit-status", &["magit"]));
+        // Supports both old (code-review) and new (my-code-review / code-review-server) formats
+        registry.register(ParserEntry::new::<CodeReviewDiff>(
+            "code-review",
+            &["my-code-review", "code-review-server"],
+        ));
+        registry.register(ParserEntry::new::<JjDiff>("jj-diff", &[]));
+        registry.register(ParserEntry::new::<JjStatus>("jj-status", &[]));
+        registry.register(ParserEntry::new::<HgDiff>("hg-diff", &["hg"]));
+        registry.register(ParserEntry::new::<SaplingDiff>(
+            "sl-diff",
+            &["sl", "sapling"],
+        ));
+        registry.register(ParserEntry::new::<GithubPullRequest>(
+            "github-pr",
+            &["github"],
+        ));
+        registry.register(ParserEntry::new::<GerritChange>("gerrit", &[]));
+        registry.register(ParserEntry::new::<GitlabMergeRequest>(
+            "gitlab-mr",
+            &["gitlab"],
+        ));
+        registry
+    }
+}
+
+impl ParserRegistry {
+    pub fn new() -> Self {
+        ParserRegistry { entries: vec![] }
+    }
+
+    pub fn register(&mut self, entry: ParserEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn formats(&self) -> Vec<&'static str> {
+        self.entries.iter().map(|entry| entry.format).collect()
+    }
+
+    pub fn find(&self, format: &str) -> Option<&ParserEntry> {
+        self.entries.iter().find(|entry| entry.matches(format))
+    }
+
+    /// Returns the most confident parser for `source` along with its confidence,
+    /// or None if no parser recognizes it at all.  A `Type:` header naming a
+    /// format or one of its aliases settles it; otherwise each parser looks at
+    /// the content.
+    pub fn detect(&self, source: &str) -> Option<(ParserEntry, u8)> {
+        if let Some(diff_type) = parse_headers(source).get(&DiffHeader::Type) {
+            match self.find(diff_type) {
+                Some(entry) => return Some((*entry, 100)),
+                None => info!("Unknown Type {:?}, detecting from the content", diff_type),
+            }
+        }
+        let mut best: Option<(ParserEntry, u8)> = None;
+        for entry in self.entries.iter() {
+            let confidence = entry.detect(source);
+            info!(
+                "Parser {} detected with confidence {}",
+                entry.format, confidence
+            );
+            if confidence > 0 && best.is_none_or(|(_, best_conf)| confidence > best_conf) {
+                best = Some((*entry, confidence));
+            }
+        }
+        best
+    }
+
+    /// Parses `source`, using the first of these which is available:
+    /// 1. `forced_format`, typically from `initializationOptions`
+    /// 2. the `Format:` header of the diff itself
+    /// 3. content detection
+    pub fn parse(&self, source: &str, forced_format: Option<&str>) -> Option<ParsedDiff> {
+        let format = forced_format
+            .map(|format| format.to_string())
+            .or_else(|| parse_headers(source).remove(&DiffHeader::Format));
+
+        if let Some(format) = format {
+            return match self.find(&format) {
+                Some(entry) => {
+                    info!("Using forced format {:?}", format);
+                    entry.parse(source)
+                }
+                None => {
+                    info!("Warning! Unknown forced format {:?}", format);
+                    None
+                }
+            };
+        }
+
+        match self.detect(source) {
+            Some((entry, _)) => entry.parse(source),
+            None => {
+                info!("Warning! Unable to determine buffer type to parse!");
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use chrono::DateTime;
+use regex::Regex;
+
+use crate::parsers::code_review::CodeReviewDiff;
+use crate::parsers::gerrit::GerritChange;
+use crate::parsers::github::GithubPullRequest;
+use crate::parsers::gitlab::GitlabMergeRequest;
+use crate::parsers::utils::*;
+
+/// A single comment in a review thread.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReviewComment {
+    pub id: Option<String>,
+    pub author: String,
+    pub timestamp: Option<String>,
+    pub body: String,
+}
+
+/// A review comment and its replies, attached to a line of the diff.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReviewThread {
+    pub file_name: String,
+    /// The line in the source file the thread is on, if it's still in the diff.
+    pub source_line: Option<SourceLineNumber>,
+    /// The line of the diff buffer the thread is rendered under.
+    pub input_line: Option<InputLineNumber>,
+    pub comment: ReviewComment,
+    pub replies: Vec<ReviewComment>,
+}
+
+/// Which side of the diff a comment was left on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Side {
+    Old,
+    #[default]
+    New,
+}
+
+/// A thread from a review export along with where the review tool put it.
+/// `line` is in the old or new file depending on `side`, and is None for
+/// outdated comments.
+#[derive(Debug, Clone, Default)]
+pub struct AnchoredThread {
+    pub thread: ReviewThread,
+    pub side: Side,
+    pub line: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileStatus {
+    Added,
+    Removed,
+    #[default]
+    Modified,
+}
+
+/// A changed file from a review export.  `patch` is the unified diff body,
+/// starting at the first `@@` header.
+#[derive(Debug, Clone, Default)]
+pub struct ReviewFile {
+    pub filename: String,
+    pub status: FileStatus,
+    pub patch: Option<String>,
+}
+
+/// A review saved from a code review tool's API, in a form which can be
+/// rendered like a code-review-server buffer.
+#[derive(Debug, Clone, Default)]
+pub struct ReviewExport {
+    /// `Key: value` lines for the top of the buffer
+    pub headers: Vec<(String, String)>,
+    pub files: Vec<ReviewFile>,
+    pub threads: Vec<AnchoredThread>,
+}
+
+impl ReviewExport {
+    /// Reads any of the supported review exports.
+    pub fn from_json(source: &str) -> Option<Self> {
+        GithubPullRequest::from_json(source)
+            .map(|pr| pr.to_export())
+            .or_else(|| GerritChange::from_json(source).map(|change| change.to_export()))
+            .or_else(|| GitlabMergeRequest::from_json(source).map(|mr| mr.to_export()))
+    }
+
+    /// Renders the review the way code-review-server would, so that it can be
+    /// displayed by the editor and parsed as a `code-review` diff.  Returns the
+    /// text along with the threads, each with the line it was rendered under.
+    pub fn render(&self) -> (String, Vec<ReviewThread>) {
+        let mut lines: Vec<String> = self
+            .headers
+            .iter()
+            .map(|(key, value)| format!("{}: {}", key, value))
+            .collect();
+        // Rendered reviews are parsed with the code-review parser
+        lines.push("Type: code-review".to_string());
+        lines.push("".to_string());
+
+        let mut threads: Vec<ReviewThread> =
+            self.threads.iter().map(|a| a.thread.clone()).collect();
+
+        for file in self.files.iter() {
+            let header = match file.status {
+                FileStatus::Added => "new file",
+                FileStatus::Removed => "deleted",
+                FileStatus::Modified => "modified",
+            };
+            lines.push(format!("{}   {}", header, file.filename));
+            lines.push("".to_string());
+            let patch = match &file.patch {
+                Some(patch) => patch,
+                None => continue,
+            };
+
+            let mut old_line: u16 = 0;
+            let mut new_line: u16 = 0;
+            for patch_line in patch.lines() {
+                lines.push(patch_line.to_string());
+                if patch_line.starts_with("@@") {
+                    if let Some((old_start, _, new_start, _)) = parse_header(patch_line) {
+                        old_line = old_start;
+                        new_line = new_start;
+                    }
+                    continue;
+                }
+                if patch_line.starts_with('\\') {
+                    // \ No newline at end of file
+                    continue;
+                }
+                let (old, new) = match LineType::from_line(patch_line) {
+                    LineType::Added => (None, Some(new_line)),
+                    LineType::Removed => (Some(old_line), None),
+                    LineType::Unmodified => (Some(old_line), Some(new_line)),
+                };
+                if old.is_some() {
+                    old_line += 1;
+                }
+                if new.is_some() {
+                    new_line += 1;
+                }
+
+                for (i, anchored) in self.threads.iter().enumerate() {
+                    let anchor = match anchored.side {
+                        Side::Old => old,
+                        Side::New => new,
+                    };
+                    if anchored.thread.file_name != file.filename
+                        || anchored.line.is_none()
+                        || anchored.line != anchor
+                    {
+                        continue;
+                    }
+                    let input_line = InputLineNumber::new(lines.len().try_into().unwrap());
+                    threads[i].input_line = Some(input_line);
+                    lines.extend(render_comment_box(&threads[i]));
+                }
+            }
+        }
+        (lines.join("\n") + "\n", threads)
+    }
+
+    /// Renders and parses the review, with its threads attached.
+    pub fn parse(&self) -> Option<ParsedDiff> {
+        let (rendered, threads) = self.render();
+        let mut diff = CodeReviewDiff::parse(&rendered)?;
+        diff.threads = threads
+            .into_iter()
+            .map(|mut thread| {
+                thread.source_line = thread
+                    .input_line
+                    .and_then(|line| diff.lines_map.get(&line))
+                    .map(|(_, diff_line)| diff_line.source_line_number);
+                thread
+            })
+            .collect();
+        Some(diff)
+    }
+}
+
+/// Review tools use RFC 3339 timestamps, code-review-server shows them as
+/// `2025-05-03 01:55:55`.
+pub fn format_timestamp(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or(timestamp.to_string())
+}
+
+/// Renders a thread as the box code-review-server draws under commented lines.
+pub fn render_comment_box(thread: &ReviewThread) -> Vec<String> {
+    let mut lines = vec![
+        "    ┌─ REVIEW COMMENT ─────────────────".to_string(),
+        format!("    │ File: {}", thread.file_name),
+        format!(
+            "    │ {} {} : {}",
+            thread.comment.timestamp.as_deref().unwrap_or(""),
+            thread.comment.author,
+            thread.comment.id.as_deref().unwrap_or("")
+        ),
+        "    │".to_string(),
+        format!("    │ [{}]:", thread.comment.author),
+    ];
+    lines.extend(
+        thread
+            .comment
+            .body
+            .lines()
+            .map(|l| format!("    │   {}", l)),
+    );
+    for reply in thread.replies.iter() {
+        lines.push("    │".to_string());
+        lines.push(format!(
+            "    │ Reply by [{}]:[{}]",
+            reply.author,
+            reply.id.as_deref().unwrap_or("")
+        ));
+        lines.extend(reply.body.lines().map(|l| format!("    │   {}", l)));
+    }
+    lines.push("    └──────────────────────────────────".to_string());
+    lines
+}
+
+/// Reads a box drawn by `render_comment_box` or code-review-server back into
+/// a thread.  `lines` runs from the `┌─ REVIEW COMMENT` line to the `└` one.
+/// The file comes from the box when it names one, else `file_name`.
+pub fn parse_comment_box(lines: &[&str], file_name: &str) -> ReviewThread {
+    let header = Regex::new(r"^(\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2})?\s*(.*?) : (\S*)$").unwrap();
+    let reply = Regex::new(r"^Reply by \[(.*)\]:\[(.*)\]$").unwrap();
+    let author = Regex::new(r"^\[(.*)\]:$").unwrap();
+    let non_empty = |s: &str| (!s.is_empty()).then(|| s.to_string());
+
+    let mut thread = ReviewThread {
+        file_name: file_name.to_string(),
+        ..Default::default()
+    };
+    let mut in_body = false;
+    for line in lines.iter() {
+        let Some(content) = line.trim_start().strip_prefix('│') else {
+            continue;
+        };
+        let text = content.strip_prefix(' ').unwrap_or(content);
+        if let Some(caps) = reply.captures(text) {
+            thread.replies.push(ReviewComment {
+                author: caps[1].to_string(),
+                id: non_empty(&caps[2]),
+                ..Default::default()
+            });
+            in_body = true;
+        } else if in_body {
+            // Bodies are indented two more than the rest of the box
+            let body_line = text.strip_prefix("  ").unwrap_or(text.trim_start());
+            let comment = thread.replies.last_mut().unwrap_or(&mut thread.comment);
+            comment.body.push_str(body_line);
+            comment.body.push('\n');
+        } else if let Some(file) = text.strip_prefix("File: ") {
+            thread.file_name = file.trim().to_string();
+        } else if let Some(caps) = author.captures(text) {
+            thread.comment.author = caps[1].to_string();
+            in_body = true;
+        } else if let Some(caps) = header.captures(text) {
+            thread.comment.timestamp = caps.get(1).map(|t| t.as_str().to_string());
+            thread.comment.author = caps[2].to_string();
+            thread.comment.id = non_empty(&caps[3]);
+        }
+    }
+    // A blank line separates each reply from what's above it
+    for comment in std::iter::once(&mut thread.comment).chain(thread.replies.iter_mut()) {
+        comment.body = comment.body.trim_end().to_string();
+    }
+    thread
+}
+
+/// Reads the older comment format, which code-review drew as a
+/// `Reviewed by @author - STATE - date` or `Comment by ...` line followed by
+/// the indented body and a dashed line.
+pub fn parse_review_block(lines: &[&str], file_name: &str) -> ReviewThread {
+    let mut comment = ReviewComment::default();
+    if let Some(first) = lines.first() {
+        let by = first
+            .trim_start_matches("Reviewed by")
+            .trim_start_matches("Comment by")
+            .trim();
+        let parts: Vec<&str> = by.split(" - ").collect();
+        comment.author = parts[0].trim_start_matches('@').to_string();
+        if parts.len() > 1 {
+            comment.timestamp = parts.last().map(|t| t.to_string());
+        }
+    }
+    comment.body = lines
+        .iter()
+        .skip(1)
+        .take_while(|line| !line.starts_with("-------"))
+        .map(|line| line.strip_prefix("    ").unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string();
+    ReviewThread {
+        file_name: file_name.to_string(),
+        comment,
+        ..Default::default()
+    }
+}
@@ -1,9 +1,10 @@
-use crate::parsers::{code_review::CodeReviewDiff, magit::MagitDiff};
-use crate::SupportedFileType;
+use crate::parsers::registry::ParserRegistry;
+use crate::parsers::review::ReviewThread;
+use crate::vcs::Vcs;
 use chrono::{DateTime, Utc};
-use log::info;
 use regex::Regex;
 use std::collections::HashMap;
+use std::str::FromStr;
 
 use strum_macros::EnumString;
 
@@ -47,6 +48,19 @@ pub struct DiffLine {
     pub source_line_number: SourceLineNumber,
 }
 
+impl DiffLine {
+    /// The text of the line in the file, without the `+`, `-` or space the
+    /// diff marks it with.
+    pub fn content(&self) -> &str {
+        let marker = match self.line_type {
+            LineType::Added => '+',
+            LineType::Removed => '-',
+            LineType::Unmodified => ' ',
+        };
+        self.line.strip_prefix(marker).unwrap_or(&self.line)
+    }
+}
+
 pub fn parse_header(header: &str) -> Option<(u16, u16, u16, u16)> {
     // Complex regex to support when the code is added at the start of a file, and we don't have all 4 values
     let re = Regex::new(r"@@ -(\d+)(,(\d+))? \+(\d+)(,(\d+))? @@").unwrap();
@@ -73,9 +87,11 @@ pub fn parse_header(header: &str) -> Option<(u16, u16, u16, u16)> {
 pub struct SourceMap {
     pub file_name: String,
     pub source_line: SourceLineNumber,
-    pub file_type: SupportedFileType,
     pub source_line_type: LineType,
     pub source_line_text: String,
+    /// How many lines the hunk has moved since the diff was taken, already
+    /// added to `source_line`
+    pub offset: i32,
 }
 
 /// The various information headers at the top of diffs which say what the diff
@@ -89,13 +105,49 @@ pub enum DiffHeader {
     Head,
     Merge,
     Push,
+    // `base ... head` branches of a pull or merge request
+    Refs,
     Draft,
     State,
+    Format,
+    // jj identifies revisions by a change id which survives rewrites, along
+    // with the commit id of the current rewrite.  hg and sl diffs record their
+    // revisions as a commit id and parent.
+    ChangeId,
+    CommitId,
+    ParentChangeId,
+}
+
+/// Reads the header block at the top of a diff, stopping at the first line
+/// which isn't a `Key: value` pair.  Unknown keys are skipped.
+pub fn parse_headers(source: &str) -> HashMap<DiffHeader, String> {
+    let re = Regex::new(r"(\w+):\s+(.+)").unwrap();
+    let mut headers = HashMap::new();
+    for line in source.lines() {
+        match re.captures(line) {
+            Some(caps) => {
+                if let Ok(header) = DiffHeader::from_str(&caps[1]) {
+                    headers.insert(header, caps[2].trim().to_string());
+                }
+            }
+            None => break,
+        }
+    }
+    headers
 }
 
 pub trait Parsable {
     fn parse(source: &str) -> Option<ParsedDiff>;
 
+    /// How confident this parser is that it understands `source`, from 0 (not
+    /// at all) to 100 (certain).  Used by the `ParserRegistry` to pick a parser.
+    fn detect(source: &str) -> u8;
+
+    /// The version control system which produces this format.
+    fn vcs() -> Vcs {
+        Vcs::Git
+    }
+
     // for when I remove the ParsedDiff type
     // fn map_diff_line_to_src(&self, line_num: u16) -> Option<SourceMap>;
 }
@@ -129,43 +181,98 @@ pub struct ParsedDiff {
     pub lines_map: HashMap<InputLineNumber, (String, DiffLine)>,
     pub parsed_at: DateTime<Utc>, // used for debugging my server
     pub total_lines: usize,       // temp deubgger
+    pub vcs: Vcs,
+    pub threads: Vec<ReviewThread>,
+    // lines of hunks found elsewhere in their file, and how far they moved
+    pub offsets: HashMap<InputLineNumber, i32>,
 }
 
 impl ParsedDiff {
+    /// The revision the diff was taken at, if the buffer recorded one.
+    pub fn revision(&self) -> Option<&String> {
+        self.headers
+            .get(&DiffHeader::ChangeId)
+            .or_else(|| self.headers.get(&DiffHeader::CommitId))
+    }
+
+    /// The revision the new side of the diff is at: a commit hash in `Head`,
+    /// the head branch of `Refs`, the commit of an export or jj change, or
+    /// the branch a magit `Head` names.
+    pub fn source_revision(&self) -> Option<String> {
+        let head = self
+            .headers
+            .get(&DiffHeader::Head)
+            .and_then(|head| head.split_whitespace().next());
+        let is_hash = |revision: &&str| {
+            revision.len() >= 7 && revision.chars().all(|c| c.is_ascii_hexdigit())
+        };
+        head.filter(is_hash)
+            .or_else(|| {
+                self.headers
+                    .get(&DiffHeader::Refs)
+                    .and_then(|refs| refs.split("...").nth(1))
+                    .and_then(|head| head.split_whitespace().next())
+            })
+            .or_else(|| self.headers.get(&DiffHeader::CommitId).map(String::as_str))
+            .or_else(|| self.headers.get(&DiffHeader::ChangeId).map(String::as_str))
+            .or(head)
+            .map(str::to_string)
+    }
+
     pub fn map_diff_line_to_src(&self, line_num: u16) -> Option<SourceMap> {
-        if let Some((filename, diff_line)) = self.lines_map.get(&InputLineNumber::new(line_num)) {
-            if let Some(file_type) = SupportedFileType::from_filename(filename.to_string()) {
-                return Some(SourceMap {
-                    file_name: filename.clone(),
-                    source_line: diff_line.source_line_number,
-                    file_type,
-                    source_line_type: diff_line.line_type,
-                    source_line_text: diff_line.line.clone(),
-                });
-            }
+        let input_line = InputLineNumber::new(line_num);
+        if let Some((filename, diff_line)) = self.lines_map.get(&input_line) {
+            let offset = self.offsets.get(&input_line).copied().unwrap_or(0);
+            let source_line = (diff_line.source_line_number.0 as i32 + offset).max(1);
+            return Some(SourceMap {
+                file_name: filename.clone(),
+                source_line: SourceLineNumber(source_line as u16),
+                source_line_type: diff_line.line_type,
+                source_line_text: diff_line.line.clone(),
+                offset,
+            });
         }
         None
     }
+
+    /// The diff line showing `source_line` of `file_name`, the other way to
+    /// `map_diff_line_to_src`.  Removed lines aren't in the file, so are
+    /// never found.
+    pub fn map_src_line_to_diff(
+        &self,
+        file_name: &str,
+        source_line: SourceLineNumber,
+    ) -> Option<(InputLineNumber, &DiffLine)> {
+        self.lines_map
+            .iter()
+            .filter(|(_, (filename, diff_line))| {
+                filename == file_name && diff_line.line_type != LineType::Removed
+            })
+            .find(|(input_line, (_, diff_line))| {
+                let offset = self.offsets.get(input_line).copied().unwrap_or(0);
+                (diff_line.source_line_number.0 as i32 + offset).max(1) == source_line.0 as i32
+            })
+            .map(|(input_line, (_, diff_line))| (*input_line, diff_line))
+    }
 }
 
 impl Parsable for ParsedDiff {
     fn parse(source: &str) -> Option<ParsedDiff> {
-        if source.contains("Type: code-review") || source.contains("Type: my-code-review") {
-            // Supports both old (code-review) and new (my-code-review / code-review-server) formats
-            CodeReviewDiff::parse(source)
-        } else if source.contains("Type: magit-status") {
-            MagitDiff::parse(source)
-        } else {
-            info!("Warning! Unable to determine buffer type to parse!");
-            None
-        }
+        ParserRegistry::default().parse(source, None)
+    }
+
+    fn detect(source: &str) -> u8 {
+        ParserRegistry::default()
+            .detect(source)
+            .map_or(0, |(_, confidence)| confidence)
     }
 }
 
 pub fn is_file_header(line: &str) -> bool {
     // Handle variable whitespace - new code-review-server format uses more spaces
-    return line.starts_with("modified ")
+    line.starts_with("modified ")
         || line.starts_with("new file ")
         || line.starts_with("deleted ")
-        || line.starts_with("diff --git ");
+        || line.starts_with("diff --git ")
+        || line.starts_with("diff -r ")
 }
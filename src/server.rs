@@ -1,15 +1,15 @@
 use serde::{Deserialize, Serialize};
 use std::fs::read_to_string;
-use strum::IntoEnumIterator;
 
 use expanduser::expanduser;
 use log::info;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use itertools::Itertools;
-use serde_json::Value;
+use regex::Regex;
+use serde_json::{json, Value};
 use tower_lsp::jsonrpc::{Error as LspError, ErrorCode, Result as LspResult};
 use tower_lsp::lsp_types::notification::Notification;
 use tower_lsp::lsp_types::request::{GotoTypeDefinitionParams, GotoTypeDefinitionResponse};
@@ -18,13 +18,29 @@ use tower_lsp::{Client, LanguageServer};
 
 use anyhow::{anyhow, Result};
 
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 
 use crate::client;
-use crate::utils::{fetch_origin_nonblocking, get_unique_elements};
+use crate::client::DocumentHolder;
+use crate::config::{FetchConfig, HunkConfig, Language, LanguageRegistry, WorktreeConfig};
+use crate::document::apply_change;
+use crate::health::BackendStatus;
+use crate::hunks::{hunks, realign, HunkStatus};
+use crate::merge::{merge_capabilities, merge_goto_responses, merge_locations};
+use crate::parsers::jj::renamed_to;
+use crate::parsers::registry::ParserRegistry;
+use crate::parsers::review::{ReviewComment, ReviewExport, ReviewThread};
+use crate::parsers::utils::*;
+use crate::utils::get_unique_elements;
+use crate::vcs::{
+    current_revision, fetch, find_repo_root, is_checked_out, resolve_revision, show_file, Vcs,
+};
+use crate::worktree::{close_review_worktree, ensure_review_worktree, prune_review_worktrees};
 
-use crate::SupportedFileType;
 use crate::*;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -50,23 +66,193 @@ impl Notification for CustomNotification {
     const METHOD: &'static str = "custom/notification";
 }
 
+/// Sent after every fetch, so editors can show when the remote was last
+/// updated or why it couldn't be.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FetchedParams {
+    pub root: String,
+    // The command which ran, if it succeeded
+    pub command: Option<String>,
+    pub error: Option<String>,
+}
+
+pub enum FetchedNotification {}
+
+impl Notification for FetchedNotification {
+    type Params = FetchedParams;
+
+    const METHOD: &'static str = "diff-lsp/fetched";
+}
+
+/// Fetches at `root`, telling the editor how it went.  Fetches already running
+/// for the session aren't started again.
+async fn run_fetch(
+    client: &Client,
+    root: &str,
+    config: &FetchConfig,
+    lock: &Mutex<()>,
+) -> Result<Option<String>> {
+    let _fetching = match lock.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            info!("Already fetching {:?}", root);
+            return Ok(None);
+        }
+    };
+    let result = fetch(root, config).await;
+    match &result {
+        Ok(None) => return result,
+        Ok(Some(command)) => {
+            client
+                .log_message(MessageType::INFO, format!("Fetched: {}", command))
+                .await
+        }
+        Err(e) => {
+            info!("Fetch failed: {:?}", e);
+            client
+                .show_message(MessageType::WARNING, format!("diff-lsp: {}", e))
+                .await
+        }
+    }
+    client
+        .send_notification::<FetchedNotification>(FetchedParams {
+            root: root.to_string(),
+            command: result.as_ref().ok().cloned().flatten(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        })
+        .await;
+    result
+}
+
+pub type BackendClient = Arc<Mutex<client::ClientForBackendServer>>;
+
+/// Each language gets its own backends for every project root in the diff.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct BackendKey {
+    pub language: String,
+    pub root: String,
+}
+
+/// The running backends of each language and project root, along with their
+/// names.
+pub type BackendMap = HashMap<BackendKey, Vec<(String, BackendClient)>>;
+
+/// Backends shared by every session of a daemon, so a new review buffer gets
+/// warm backends for projects another session already opened.
+pub type SharedBackends = Arc<Mutex<BackendMap>>;
+
+/// The root of each diff with a review worktree, across every session of a
+/// daemon, so one session doesn't remove a worktree another is reviewing in.
+pub type DiffRoots = Arc<Mutex<HashMap<DocumentHolder, String>>>;
+
+/// A lock for each BackendKey whose backends are being started, held until
+/// they're running.  Sessions wanting the same backends wait on it rather
+/// than on every other session's use of the map.
+pub type StartingBackends = Arc<Mutex<HashMap<BackendKey, Arc<Mutex<()>>>>>;
+
+/// The text of files, None if they couldn't be read, by uri and the revision
+/// read from, None for the working copy.
+type SourceTexts = HashMap<(Url, Option<String>), Option<String>>;
+
+/// The error for a request no backend can answer for the file it's on.
+fn unavailable(message: String) -> LspError {
+    LspError {
+        code: ErrorCode::MethodNotFound,
+        message: message.into(),
+        data: None,
+    }
+}
+
+/// Logs a backend's failure to answer `method`, so the others' answers can
+/// still be used.
+fn backend_failed(method: &str, backend: &client::ClientForBackendServer, e: anyhow::Error) {
+    info!("{} failed in {}: {}", method, backend.lsp_command, e);
+}
+
+/// The error for a request when every backend asked failed, rather than
+/// only some of them.
+fn all_failed(method: &str, failures: usize, backends: usize) -> LspResult<()> {
+    if failures > 0 && failures == backends {
+        return Err(LspError {
+            code: ErrorCode::ServerError(1),
+            message: format!("Every backend failed to answer {}", method).into(),
+            data: None,
+        });
+    }
+    Ok(())
+}
+
+/// Starts each of `language`'s backends in `dir`.  One which can't be started
+/// is logged and left out, so it's only an error if none of them could be.
+pub fn start_backends(language: &Language, dir: &str) -> Result<Vec<(String, BackendClient)>> {
+    let mut started = vec![];
+    let mut failures = vec![];
+    for backend in language.backends.iter() {
+        info!(
+            "Starting client for server: {:?} in dir {:?}",
+            backend.command, dir
+        );
+        match client::ClientForBackendServer::new(backend, dir) {
+            Ok(client) => started.push((backend.name.clone(), Arc::new(Mutex::new(client)))),
+            Err(e) => {
+                info!(
+                    "Skipping {} backend {:?}: {:?}",
+                    language.name, backend.name, e
+                );
+                failures.push(e);
+            }
+        }
+    }
+    match failures.pop() {
+        Some(e) if started.is_empty() => Err(e),
+        _ => Ok(started),
+    }
+}
+
+/// Starts the backends for `active_langs` in `dir`.  Files in other project
+/// roots get their backends once the diff is opened.  Backends which aren't
+/// installed or can't be started are logged and skipped so the rest of the
+/// language's, and other languages', still work.
 pub fn create_backends_map(
-    active_langs: Vec<SupportedFileType>,
+    active_langs: Vec<String>,
     dir: &str,
-) -> Result<HashMap<SupportedFileType, Arc<Mutex<client::ClientForBackendServer>>>> {
-    let mut backends: HashMap<SupportedFileType, Arc<Mutex<client::ClientForBackendServer>>> =
-        HashMap::new();
-
-    for supported_lang in SupportedFileType::iter() {
-        if active_langs.contains(&supported_lang) {
-            let (command, args) = get_lsp_for_file_type(supported_lang);
-            info!("Starting client for server: {:?} in dir {:?}", command, dir);
-            backends.insert(
-                supported_lang,
-                Arc::new(Mutex::new(client::ClientForBackendServer::new(
-                    command, args, dir,
-                )?)),
-            );
+    languages: &LanguageRegistry,
+) -> Result<BackendMap> {
+    let mut backends: BackendMap = HashMap::new();
+
+    for language in languages.languages() {
+        if active_langs.contains(&language.name) {
+            let mut available = language.clone();
+            available.backends.retain(|backend| {
+                let status = BackendStatus::check(&language.name, backend);
+                if status.is_available() {
+                    info!(
+                        "Found {} at {:?}, version {:?}, error {:?}",
+                        status.name, status.path, status.version, status.error
+                    );
+                } else {
+                    info!(
+                        "Skipping {} backend {:?}: {:?} was not found",
+                        language.name, backend.name, status.command
+                    );
+                }
+                status.is_available()
+            });
+            if available.backends.is_empty() {
+                continue;
+            }
+            match start_backends(&available, dir) {
+                Ok(started) => {
+                    backends.insert(
+                        BackendKey {
+                            language: language.name.clone(),
+                            root: dir.to_string(),
+                        },
+                        started,
+                    );
+                }
+                Err(e) => info!("Skipping {} backends: {:?}", language.name, e),
+            }
         }
     }
     Ok(backends)
@@ -74,17 +260,42 @@ pub fn create_backends_map(
 
 pub fn read_initialization_params_from_tempfile(
     file_path: &PathBuf,
-) -> Result<(String, Option<String>, Vec<SupportedFileType>)> {
-    if let Ok(input) = read_to_string(file_path) {
+    languages: &LanguageRegistry,
+) -> Result<(String, Option<String>, Vec<String>)> {
+    if let Ok(mut input) = read_to_string(file_path) {
+        if let Some(review) = ReviewExport::from_json(&input) {
+            // Scan the rendered review so the file headers below match
+            input = review.render().0;
+        }
         let mut cwd = String::new();
         let mut worktree: Option<String> = None;
-        let mut file_types: Vec<SupportedFileType> = vec![];
+        let mut filenames: Vec<String> = vec![];
         let root_regex = Regex::new(r"^Root:\s(.*)").unwrap();
         let worktree_regex = Regex::new(r"^Worktree:\s(.*)").unwrap();
         let file_regex = Regex::new(r"^(modified|new file|deleted)\s+(.*)").unwrap();
         let diff_git_regex = Regex::new(r"^diff --git\s+(.*)").unwrap();
+        let diff_hg_regex = Regex::new(r"^diff -r \w+(?: -r \w+)? (.*)").unwrap();
+        // jj diff's color-words file headers
+        let jj_file_regex = Regex::new(
+            r"^(?:Modified|Added|Removed) (?:regular file|executable file|symlink) (.*):$",
+        )
+        .unwrap();
+        // jj status' changed files, only read under `Working copy changes:`
+        let jj_status_regex = Regex::new(r"^[MADRC] (.+)$").unwrap();
+        let mut in_jj_status = false;
 
         for line in input.lines() {
+            if line.starts_with("Working copy changes:") {
+                in_jj_status = true;
+                continue;
+            }
+            if in_jj_status {
+                if let Some(caps) = jj_status_regex.captures(line) {
+                    filenames.push(renamed_to(&caps[1]));
+                    continue;
+                }
+                in_jj_status = false;
+            }
             if let Some(caps) = root_regex.captures(line) {
                 cwd = caps.get(1).unwrap().as_str().to_string();
                 // break;
@@ -94,10 +305,7 @@ pub fn read_initialization_params_from_tempfile(
             }
             if let Some(caps) = file_regex.captures(line) {
                 println!("caps: {:?}", caps.len());
-                let filename = caps.get(2).unwrap().as_str().to_string();
-                if let Some(file_type) = SupportedFileType::from_filename(filename) {
-                    file_types.push(file_type);
-                }
+                filenames.push(caps.get(2).unwrap().as_str().to_string());
             } else if let Some(caps) = diff_git_regex.captures(line) {
                 // Handle diff --git a/foo.rs b/foo.rs
                 // We want the last one, and strip b/
@@ -108,53 +316,543 @@ pub fn read_initialization_params_from_tempfile(
                 } else {
                     last_file.to_string()
                 };
-                if let Some(file_type) = SupportedFileType::from_filename(filename) {
-                    file_types.push(file_type);
-                }
+                filenames.push(filename);
+            } else if let Some(caps) = diff_hg_regex.captures(line) {
+                filenames.push(caps.get(1).unwrap().as_str().to_string());
+            } else if let Some(caps) = jj_file_regex.captures(line) {
+                filenames.push(caps[1].to_string());
+            }
+        }
+        if cwd.is_empty() {
+            // jj buffers don't always say where they came from, so use the repo we were started in.
+            if let Some((root, vcs)) = std::env::current_dir()
+                .ok()
+                .and_then(|dir| find_repo_root(&dir))
+            {
+                info!("No Root: given, using {:?} repo at {:?}", vcs, root);
+                cwd = root.display().to_string();
             }
         }
         let expanded_cwd = expanduser(cwd)?
             .into_os_string()
             .into_string()
             .map_err(|_| anyhow!("Failed to convert path to string"))?;
+        // Scripts without an extension are found by their #! line, which needs the root
+        let file_types: Vec<String> = filenames
+            .iter()
+            .filter_map(|filename| languages.detect(&expanded_cwd, filename))
+            .map(|language| language.name.clone())
+            .collect();
         Ok((expanded_cwd, worktree, get_unique_elements(&file_types)))
     } else {
         return Err(anyhow!("Unable to read input tempfile"));
     }
 }
 
+/// What the editor can send as `initializationOptions`, in place of the
+/// fields of the initialization tempfile.
+#[derive(Debug, Default, Deserialize)]
+pub struct InitializationOptions {
+    pub root: Option<String>,
+    pub worktree: Option<String>,
+    // Languages whose backends are started straight away, rather than when a
+    // diff with their files is opened
+    pub languages: Option<Vec<String>>,
+    pub format: Option<String>,
+}
+
+/// The contents of `filename`, relative to `root`, at `revision` or else in
+/// the working copy.  Revisions are read by running the VCS, off the async
+/// runtime.
+async fn source_text(
+    root: &str,
+    filename: &str,
+    revision: Option<&(Vcs, String)>,
+) -> Option<String> {
+    match revision {
+        Some((vcs, revision)) => {
+            let (root, vcs, revision, filename) = (
+                root.to_string(),
+                *vcs,
+                revision.clone(),
+                filename.to_string(),
+            );
+            tokio::task::spawn_blocking(move || match show_file(&root, vcs, &revision, &filename) {
+                Ok(text) => Some(text),
+                Err(e) => {
+                    info!("Could not read {:?} at {}: {}", filename, revision, e);
+                    None
+                }
+            })
+            .await
+            .ok()
+            .flatten()
+        }
+        None => {
+            let uri = uri_from_relative_filename(root.to_string(), filename);
+            fs::read_to_string(uri.path()).ok()
+        }
+    }
+}
+
+/// The document uri commands take as their first argument.
+fn uri_argument(params: &ExecuteCommandParams) -> LspResult<Url> {
+    params
+        .arguments
+        .first()
+        .and_then(|arg| arg.as_str())
+        .and_then(|arg| Url::parse(arg).ok())
+        .ok_or(LspError::invalid_params(format!(
+            "{} expects a document uri",
+            params.command
+        )))
+}
+
+/// Where `thread` is, 0-based: its line in the file, moved along with its
+/// hunk if that was realigned, and its line in the diff buffer.
+fn thread_lines(diff: &ParsedDiff, thread: &ReviewThread) -> (Option<u32>, Option<u32>) {
+    let diff_line = thread.input_line.map(|line| line.0 as u32 - 1);
+    let line = match thread
+        .input_line
+        .and_then(|line| diff.map_diff_line_to_src(line.0))
+    {
+        Some(source_map) => Some(source_map.source_line.0 as u32 - 1),
+        None => thread
+            .source_line
+            .map(|line| (line.0 as u32).saturating_sub(1)),
+    };
+    (line, diff_line)
+}
+
+/// A review thread as the `threads` command returns it.  Lines are 0-based,
+/// `line` in the file and `diffLine` in the diff buffer; either is null when
+/// the thread isn't on a line of the diff.
+fn thread_json(diff: &ParsedDiff, thread: &ReviewThread) -> Value {
+    let comment_json = |comment: &ReviewComment| {
+        json!({
+            "id": comment.id,
+            "author": comment.author,
+            "timestamp": comment.timestamp,
+            "body": comment.body,
+        })
+    };
+    let mut value = comment_json(&thread.comment);
+    value["file"] = json!(thread.file_name);
+    let (line, diff_line) = thread_lines(diff, thread);
+    value["line"] = json!(line);
+    value["diffLine"] = json!(diff_line);
+    value["replies"] = thread.replies.iter().map(comment_json).collect();
+    value
+}
+
+/// Where backends run: `worktree` inside `root` when it exists, else `root`.
+pub fn backend_root(root: &str, worktree: Option<&str>) -> String {
+    if let Some(wt) = worktree {
+        let wt_path = Path::new(root).join(wt);
+        if wt_path.exists() {
+            info!("Using worktree at {:?}", wt_path);
+            return wt_path.display().to_string();
+        }
+        info!(
+            "Worktree {:?} does not exist, falling back to root",
+            wt_path
+        );
+    }
+    root.to_string()
+}
+
+/// The revision a diff names and what it resolved to, if that isn't what's
+/// checked out.
+#[derive(Debug, Clone)]
+struct ReviewRevision {
+    named: String,
+    resolved: Option<(Vcs, String)>,
+}
+
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug)]
 pub struct DiffLsp {
     pub client: Client,
-    pub backends: HashMap<SupportedFileType, Arc<Mutex<client::ClientForBackendServer>>>,
+    pub backends: SharedBackends,
+    pub starting: StartingBackends,
+    // The backends this session has asked for, which are the ones it
+    // initializes and tells about changed files
+    backend_keys: Mutex<HashSet<BackendKey>>,
     pub diff_map: Mutex<HashMap<Url, ParsedDiff>>,
-    pub root: String, // The project root, without a trailing slash.  ~/diff-lsp for example
+    // The text of each open diff as the editor has it, kept up to date by
+    // didChange.  Diffs are parsed from this rather than the file on disk.
+    pub documents: Mutex<HashMap<Url, String>>,
+    // The project root, without a trailing slash.  ~/diff-lsp for example.  Empty
+    // until the editor's initializationOptions say where it is.
+    root: RwLock<String>,
+    pub languages: LanguageRegistry,
+    pub parsers: ParserRegistry,
+    // Set from the `format` initialization option to skip content detection
+    pub forced_format: Mutex<Option<String>>,
+    pub fetch: FetchConfig,
+    fetch_lock: Arc<Mutex<()>>,
+    // Fetches every `fetch.interval` until shutdown
+    fetch_task: Mutex<Option<JoinHandle<()>>>,
+    // Whether the editor can be asked to watch files for us
+    can_watch_files: AtomicBool,
+    // Tells this session's hold on backend documents from other sessions'
+    session: u64,
+    pub worktrees: WorktreeConfig,
+    pub hunks: HunkConfig,
+    // Diagnostics last sent for each diff, so unchanged ones aren't sent again
+    pub published: Mutex<HashMap<Url, Vec<Diagnostic>>>,
+    // The root of each diff with a review worktree, in place of the session's
+    pub diff_roots: DiffRoots,
+    // The jj change checked out at the root, looked up at most once per
+    // open, refresh or fetch rather than on every edit
+    jj_change: Mutex<Option<Option<String>>>,
+    // The revision each diff names, and what it resolved to if it isn't
+    // checked out, kept until the diff is next opened, refreshed or fetched
+    review_revisions: Mutex<HashMap<Url, ReviewRevision>>,
+    // Files' texts, so edits to a diff don't read them again.  Cleared when a
+    // diff is opened or refreshed and when watched files change.
+    source_texts: Mutex<SourceTexts>,
 }
 
 impl DiffLsp {
     pub fn new(
         client: Client,
-        backends: HashMap<SupportedFileType, Arc<Mutex<client::ClientForBackendServer>>>,
+        backends: BackendMap,
+        root: String,
+        languages: LanguageRegistry,
+    ) -> Self {
+        Self::with_shared_backends(client, Arc::new(Mutex::new(backends)), root, languages)
+    }
+
+    /// A session using backends which other sessions may also be using.
+    pub fn with_shared_backends(
+        client: Client,
+        backends: SharedBackends,
         root: String,
+        languages: LanguageRegistry,
     ) -> Self {
         let server = DiffLsp {
             client,
             backends,
+            starting: Arc::new(Mutex::new(HashMap::new())),
+            backend_keys: Mutex::new(HashSet::new()),
             diff_map: Mutex::new((|| {
                 let map: HashMap<Url, ParsedDiff> = HashMap::new();
                 map
             })()),
-            root,
+            documents: Mutex::new(HashMap::new()),
+            root: RwLock::new(root),
+            languages,
+            parsers: ParserRegistry::default(),
+            forced_format: Mutex::new(None),
+            fetch: FetchConfig::default(),
+            fetch_lock: Arc::new(Mutex::new(())),
+            fetch_task: Mutex::new(None),
+            can_watch_files: AtomicBool::new(false),
+            session: NEXT_SESSION.fetch_add(1, Ordering::Relaxed),
+            worktrees: WorktreeConfig::default(),
+            hunks: HunkConfig::default(),
+            published: Mutex::new(HashMap::new()),
+            diff_roots: Arc::new(Mutex::new(HashMap::new())),
+            jj_change: Mutex::new(None),
+            review_revisions: Mutex::new(HashMap::new()),
+            source_texts: Mutex::new(HashMap::new()),
         };
         info!("Starting server: {:?}", server);
         server
     }
 
-    fn get_backend(
+    pub fn root(&self) -> String {
+        self.root.read().unwrap().clone()
+    }
+
+    /// Takes the root from the initializationOptions, falling back to the one
+    /// given on startup, then the editor's rootUri or first workspace folder.
+    #[allow(deprecated)] // root_path is the fallback for old clients
+    fn set_root(
         &self,
+        options: &InitializationOptions,
+        params: &InitializeParams,
+    ) -> LspResult<()> {
+        let root = match options.root.as_deref() {
+            Some(root) => expanduser(root)
+                .map_err(|e| LspError::invalid_params(format!("Invalid root {:?}: {}", root, e)))?
+                .display()
+                .to_string(),
+            None if !self.root().is_empty() => return Ok(()),
+            None => match params
+                .root_uri
+                .as_ref()
+                .or(params
+                    .workspace_folders
+                    .as_ref()
+                    .and_then(|folders| folders.first())
+                    .map(|folder| &folder.uri))
+                .and_then(|uri| uri.to_file_path().ok())
+                .or(params.root_path.as_ref().map(PathBuf::from))
+            {
+                Some(path) => path.display().to_string(),
+                None => return Ok(()),
+            },
+        };
+        let root = backend_root(root.trim_end_matches('/'), options.worktree.as_deref());
+        *self.root.write().unwrap() = root;
+        Ok(())
+    }
+
+    /// Fetches in the background if fetching is enabled.
+    fn start_fetch(&self) {
+        let root = self.root();
+        if !self.fetch.enabled || root.is_empty() {
+            return;
+        }
+        let (client, config, lock) = (
+            self.client.clone(),
+            self.fetch.clone(),
+            self.fetch_lock.clone(),
+        );
+        tokio::spawn(async move {
+            let _ = run_fetch(&client, &root, &config, &lock).await;
+        });
+    }
+
+    /// Fetches every `fetch.interval` seconds for as long as the session lasts.
+    async fn start_periodic_fetch(&self) {
+        let root = self.root();
+        let interval = match self.fetch.interval {
+            Some(interval) if self.fetch.enabled && interval > 0 && !root.is_empty() => interval,
+            _ => return,
+        };
+        let (client, config, lock) = (
+            self.client.clone(),
+            self.fetch.clone(),
+            self.fetch_lock.clone(),
+        );
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+            // The first tick is immediate, and startup already fetched
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let _ = run_fetch(&client, &root, &config, &lock).await;
+            }
+        });
+        if let Some(previous) = self.fetch_task.lock().await.replace(task) {
+            previous.abort();
+        }
+    }
+
+    fn backend_key(&self, root: &str, language: &Language, file_name: &str) -> BackendKey {
+        BackendKey {
+            language: language.name.clone(),
+            root: language.project_root(root, file_name),
+        }
+    }
+
+    /// The backends for the file `source_map` points into which handle
+    /// `method`, most preferred first.  The error says why there are none.
+    async fn get_backends(
+        &self,
+        root: &str,
         source_map: &SourceMap,
-    ) -> Option<&Arc<Mutex<client::ClientForBackendServer>>> {
-        self.backends.get(&source_map.file_type)
+        method: &str,
+    ) -> LspResult<Vec<BackendClient>> {
+        let language = self
+            .languages
+            .detect(root, &source_map.file_name)
+            .ok_or_else(|| {
+                unavailable(format!(
+                    "No language is configured for {}",
+                    source_map.file_name
+                ))
+            })?;
+        let handled_by: Vec<&String> = language
+            .backends
+            .iter()
+            .filter(|backend| backend.handles(method))
+            .map(|backend| &backend.name)
+            .collect();
+        if handled_by.is_empty() {
+            return Err(unavailable(format!(
+                "No {} backend is configured for {}",
+                language.name, method
+            )));
+        }
+        let key = self.backend_key(root, language, &source_map.file_name);
+        let candidates: Vec<BackendClient> = self
+            .backends
+            .lock()
+            .await
+            .get(&key)
+            .ok_or_else(|| {
+                unavailable(format!(
+                    "No {} backend is running for {}",
+                    language.name, key.root
+                ))
+            })?
+            .iter()
+            .filter(|(name, _)| handled_by.contains(&name))
+            .map(|(_, backend)| backend.clone())
+            .collect();
+        let mut supported = vec![];
+        for backend_mutex in candidates {
+            if backend_mutex.lock().await.supports(method) {
+                supported.push(backend_mutex);
+            }
+        }
+        if supported.is_empty() {
+            return Err(unavailable(format!(
+                "No {} backend supports {}",
+                language.name, method
+            )));
+        }
+        Ok(supported)
+    }
+
+    async fn all_backends(&self) -> Vec<BackendClient> {
+        self.backends
+            .lock()
+            .await
+            .values()
+            .flatten()
+            .map(|(_, backend)| backend.clone())
+            .collect()
+    }
+
+    /// The backends this session has asked for.  Backends shared between
+    /// project roots are only listed once.
+    async fn session_backends(&self) -> Vec<BackendClient> {
+        let keys = self.backend_keys.lock().await.clone();
+        let backends = self.backends.lock().await;
+        let mut session_backends: Vec<BackendClient> = vec![];
+        for (_, backend) in keys.iter().filter_map(|key| backends.get(key)).flatten() {
+            if !session_backends
+                .iter()
+                .any(|other| Arc::ptr_eq(other, backend))
+            {
+                session_backends.push(backend.clone());
+            }
+        }
+        session_backends
+    }
+
+    /// The backends already running for `file_name`'s project root.
+    async fn running_backends(&self, root: &str, file_name: &str) -> Vec<BackendClient> {
+        let Some(language) = self.languages.detect(root, file_name) else {
+            return vec![];
+        };
+        let key = self.backend_key(root, language, file_name);
+        match self.backends.lock().await.get(&key) {
+            Some(backends) => backends
+                .iter()
+                .map(|(_, backend)| backend.clone())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// The backends for `file_name`'s project root, starting them if this is
+    /// the first file seen there.  The map is only locked to look backends up
+    /// and add them, so starting them doesn't hold up other sessions.
+    async fn get_or_start_backends(&self, root: &str, file_name: &str) -> Vec<BackendClient> {
+        let language = match self.languages.detect(root, file_name) {
+            Some(language) => language,
+            None => return vec![],
+        };
+        let key = self.backend_key(root, language, file_name);
+        self.backend_keys.lock().await.insert(key.clone());
+        let starting = self.starting_lock(&key).await;
+        let _starting = starting.lock().await;
+        if let Some(running) = self.backends.lock().await.get(&key) {
+            return running.iter().map(|(_, backend)| backend.clone()).collect();
+        }
+
+        let backends = match self.shared_backends(&key).await {
+            Some(shared) => {
+                info!(
+                    "Adding {:?} as a workspace folder of the {} backends",
+                    key.root, key.language
+                );
+                for (_, backend_mutex) in shared.iter() {
+                    let mut backend = backend_mutex.lock().await;
+                    backend.add_workspace_folder(Path::new(&key.root));
+                }
+                shared
+            }
+            None => {
+                info!(
+                    "Starting {} backends for project at {:?}",
+                    key.language, key.root
+                );
+                let (language, root) = (language.clone(), key.root.clone());
+                let started = tokio::task::spawn_blocking(move || {
+                    let started = start_backends(&language, &root)?;
+                    for (_, backend_mutex) in started.iter() {
+                        let mut backend = backend_mutex.blocking_lock();
+                        if let Err(e) = backend.initialize() {
+                            info!("Failed to initialize {:?}: {:?}", backend.lsp_command, e);
+                        }
+                        backend.initialized();
+                    }
+                    Ok::<_, anyhow::Error>(started)
+                })
+                .await;
+                match started {
+                    Ok(Ok(started)) => started,
+                    Ok(Err(e)) => {
+                        info!("Failed to start backends at {:?}: {:?}", key.root, e);
+                        return vec![];
+                    }
+                    Err(e) => {
+                        info!("Starting backends at {:?} panicked: {:?}", key.root, e);
+                        return vec![];
+                    }
+                }
+            }
+        };
+        self.backends
+            .lock()
+            .await
+            .insert(key.clone(), backends.clone());
+        backends.into_iter().map(|(_, backend)| backend).collect()
+    }
+
+    /// The lock held while `key`'s backends are started.
+    async fn starting_lock(&self, key: &BackendKey) -> Arc<Mutex<()>> {
+        self.starting
+            .lock()
+            .await
+            .entry(key.clone())
+            .or_default()
+            .clone()
+    }
+
+    /// Running backends for `key`'s language in the same repository which can
+    /// take its root as another workspace folder instead of starting new ones.
+    async fn shared_backends(&self, key: &BackendKey) -> Option<Vec<(String, BackendClient)>> {
+        let repo = find_repo_root(Path::new(&key.root))?.0;
+        let candidates: Vec<Vec<(String, BackendClient)>> = self
+            .backends
+            .lock()
+            .await
+            .iter()
+            .filter(|(other_key, _)| other_key.language == key.language)
+            .filter(|(other_key, _)| {
+                find_repo_root(Path::new(&other_key.root)).is_some_and(|(other, _)| other == repo)
+            })
+            .map(|(_, others)| others.clone())
+            .collect();
+        for others in candidates {
+            let mut all_support = true;
+            for (_, backend_mutex) in others.iter() {
+                all_support &= backend_mutex.lock().await.supports_workspace_folders();
+            }
+            if all_support {
+                return Some(others);
+            }
+        }
+        None
     }
 
     async fn get_diff(&self, uri: &Url) -> Option<ParsedDiff> {
@@ -181,16 +879,489 @@ impl DiffLsp {
         None
     }
 
+    fn holder(&self, diff: &Url) -> DocumentHolder {
+        DocumentHolder {
+            session: self.session,
+            diff: diff.clone(),
+        }
+    }
+
+    /// Where the files of the diff at `uri` are: its review worktree if it has
+    /// one, otherwise the session's root.
+    async fn diff_root(&self, uri: &Url) -> String {
+        match self.diff_roots.lock().await.get(&self.holder(uri)) {
+            Some(root) => root.clone(),
+            None => self.root(),
+        }
+    }
+
+    /// Gives the diff at `uri` a worktree of its own when it reviews a git
+    /// revision other than the one checked out, and removes the least
+    /// recently used beyond `worktrees.max`.
+    async fn open_review_worktree(&self, uri: &Url) {
+        if !self.worktrees.enabled {
+            return;
+        }
+        let Some(diff) = self.get_diff(uri).await else {
+            return;
+        };
+        let root = self.root();
+        let (Some((repo, Vcs::Git)), Some(head), Some((_, commit))) = (
+            find_repo_root(Path::new(&root)),
+            diff.source_revision(),
+            self.review_revision(uri, &diff).await,
+        ) else {
+            return;
+        };
+        let made = {
+            let (repo, head, config) = (repo.clone(), head.clone(), self.worktrees.clone());
+            tokio::task::spawn_blocking(move || {
+                ensure_review_worktree(&repo, &head, &commit, &config)
+            })
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|made| made)
+        };
+        let worktree = match made {
+            Ok(worktree) => worktree,
+            Err(e) => {
+                info!("Could not make a worktree for {}: {:?}", head, e);
+                self.client
+                    .show_message(
+                        MessageType::WARNING,
+                        format!("diff-lsp could not make a worktree for {}: {}", head, e),
+                    )
+                    .await;
+                return;
+            }
+        };
+        // The session's root may be a folder inside the repository
+        let diff_root = match Path::new(&root).strip_prefix(&repo) {
+            Ok(relative) if !relative.as_os_str().is_empty() => worktree.join(relative),
+            _ => worktree,
+        }
+        .display()
+        .to_string();
+        info!("Reviewing {:?} in {:?}", uri, diff_root);
+        let in_use: Vec<PathBuf> = {
+            let mut diff_roots = self.diff_roots.lock().await;
+            diff_roots.insert(self.holder(uri), diff_root);
+            diff_roots.values().map(PathBuf::from).collect()
+        };
+        let config = self.worktrees.clone();
+        let pruned =
+            tokio::task::spawn_blocking(move || prune_review_worktrees(&repo, &config, &in_use))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|pruned| pruned);
+        match pruned {
+            Ok(removed) => self.drop_worktree_backends(&removed).await,
+            Err(e) => info!("Could not remove old review worktrees: {:?}", e),
+        }
+    }
+
+    /// Lets go of the review worktree of the diff at `uri`, which was closed,
+    /// removing it if no diff in any session is still using it.
+    async fn release_review_worktree(&self, uri: &Url) {
+        let (closed, in_use): (String, Vec<PathBuf>) = {
+            let mut diff_roots = self.diff_roots.lock().await;
+            let Some(closed) = diff_roots.remove(&self.holder(uri)) else {
+                return;
+            };
+            (closed, diff_roots.values().map(PathBuf::from).collect())
+        };
+        let Some((repo, Vcs::Git)) = find_repo_root(Path::new(&self.root())) else {
+            return;
+        };
+        let config = self.worktrees.clone();
+        let removed = tokio::task::spawn_blocking(move || {
+            close_review_worktree(&repo, &config, Path::new(&closed), &in_use)
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|removed| removed);
+        match removed {
+            Ok(removed) => self.drop_worktree_backends(&removed).await,
+            Err(e) => info!("Could not remove the review worktree of {}: {:?}", uri, e),
+        }
+    }
+
+    /// Forgets the backends of review worktrees which were removed, shutting
+    /// down any which aren't also serving another root.
+    async fn drop_worktree_backends(&self, removed: &[PathBuf]) {
+        if removed.is_empty() {
+            return;
+        }
+        let inside = |key: &BackendKey| {
+            removed
+                .iter()
+                .any(|path| Path::new(&key.root).starts_with(path))
+        };
+        let stopped: Vec<BackendClient> = {
+            let mut backends = self.backends.lock().await;
+            let stale: Vec<BackendKey> =
+                backends.keys().filter(|key| inside(key)).cloned().collect();
+            let mut stopped: Vec<BackendClient> = vec![];
+            for key in stale {
+                info!("Dropping the {} backends at {:?}", key.language, key.root);
+                for (_, backend) in backends.remove(&key).unwrap_or_default() {
+                    if !stopped.iter().any(|other| Arc::ptr_eq(other, &backend)) {
+                        stopped.push(backend);
+                    }
+                }
+            }
+            stopped.retain(|backend| {
+                !backends
+                    .values()
+                    .flatten()
+                    .any(|(_, other)| Arc::ptr_eq(other, backend))
+            });
+            stopped
+        };
+        self.starting.lock().await.retain(|key, _| !inside(key));
+        self.backend_keys.lock().await.retain(|key| !inside(key));
+        for backend_mutex in stopped {
+            let _ =
+                tokio::task::spawn_blocking(move || backend_mutex.blocking_lock().shutdown()).await;
+        }
+    }
+
+    /// The revision to read a diff's files from, when it names one other than
+    /// what's checked out at the root, such as the head of a pull request.
+    /// It's looked up when the diff is opened, refreshed or fetched, or names
+    /// another revision, rather than every time the diff is used.
+    async fn review_revision(&self, uri: &Url, diff: &ParsedDiff) -> Option<(Vcs, String)> {
+        let revision = diff.source_revision()?;
+        if let Some(cached) = self.review_revisions.lock().await.get(uri) {
+            if cached.named == revision {
+                return cached.resolved.clone();
+            }
+        }
+        let root = self.root();
+        let remote = self
+            .fetch
+            .remote
+            .clone()
+            .unwrap_or_else(|| "origin".to_string());
+        let named = revision.clone();
+        let resolved = tokio::task::spawn_blocking(move || {
+            let (_, vcs) = find_repo_root(Path::new(&root))?;
+            let Some(resolved) = resolve_revision(&root, vcs, &named, &remote) else {
+                info!("{} isn't in the repository, using the working copy", named);
+                return None;
+            };
+            if is_checked_out(&root, vcs, &resolved) {
+                return None;
+            }
+            Some((vcs, resolved))
+        })
+        .await
+        .ok()
+        .flatten();
+        self.review_revisions.lock().await.insert(
+            uri.clone(),
+            ReviewRevision {
+                named: revision,
+                resolved: resolved.clone(),
+            },
+        );
+        resolved
+    }
+
+    /// The text of `filename` in `root` at `revision`, read once until the
+    /// cache is next cleared.
+    async fn cached_source_text(
+        &self,
+        root: &str,
+        filename: &str,
+        revision: Option<&(Vcs, String)>,
+    ) -> Option<String> {
+        let key = (
+            uri_from_relative_filename(root.to_string(), filename),
+            revision.map(|(_, revision)| revision.clone()),
+        );
+        if let Some(text) = self.source_texts.lock().await.get(&key) {
+            return text.clone();
+        }
+        let text = source_text(root, filename, revision).await;
+        self.source_texts.lock().await.insert(key, text.clone());
+        text
+    }
+
+    /// Where the files of a diff are, and the revision to read them from when
+    /// it isn't the working copy there.  A review worktree already has the
+    /// revision checked out.
+    async fn diff_source(&self, uri: &Url, diff: &ParsedDiff) -> (String, Option<(Vcs, String)>) {
+        match self.diff_roots.lock().await.get(&self.holder(uri)) {
+            Some(root) => (root.clone(), None),
+            None => (self.root(), self.review_revision(uri, diff).await),
+        }
+    }
+
+    /// Checks each hunk of this session's diffs against its file as backends
+    /// have it.  Hunks found nearby are realigned if `hunks.realign` is on;
+    /// the rest which don't match get a warning on the diff.  Offsets are
+    /// worked out afresh each time, from where the diff itself puts hunks.
+    /// The warnings are published along with what backends last reported
+    /// on lines the diff shows.
+    async fn update_diagnostics(&self) {
+        let diffs: Vec<(Url, ParsedDiff)> = {
+            let diff_map = self.diff_map.lock().await;
+            diff_map
+                .iter()
+                .map(|(uri, diff)| (uri.clone(), diff.clone()))
+                .collect()
+        };
+        let mut all_diagnostics = HashMap::new();
+        for (diff_uri, diff) in diffs {
+            let (root, revision) = self.diff_source(&diff_uri, &diff).await;
+            let mut texts: HashMap<String, Option<String>> = HashMap::new();
+            let mut realigned = diff.clone();
+            realigned.offsets.clear();
+            let mut diagnostics = vec![];
+            for hunk in hunks(&diff) {
+                if !texts.contains_key(&hunk.filename) {
+                    let text = self
+                        .cached_source_text(&root, &hunk.filename, revision.as_ref())
+                        .await;
+                    texts.insert(hunk.filename.clone(), text);
+                }
+                let Some(text) = &texts[&hunk.filename] else {
+                    continue;
+                };
+                let file_lines: Vec<&str> = text.lines().collect();
+                let message = match hunk.check(&file_lines, self.hunks.distance, self.hunks.fuzz) {
+                    HunkStatus::Current => continue,
+                    HunkStatus::Moved(offset) if self.hunks.realign => {
+                        info!("Realigning a hunk of {} by {}", hunk.filename, offset);
+                        realign(&mut realigned, &hunk, offset);
+                        continue;
+                    }
+                    HunkStatus::Moved(offset) => format!(
+                        "{} has this hunk {} lines {} than the diff says",
+                        hunk.filename,
+                        offset.abs(),
+                        if offset > 0 { "lower" } else { "higher" }
+                    ),
+                    HunkStatus::Stale => format!(
+                        "{} no longer matches this hunk, so hovers and definitions may be wrong",
+                        hunk.filename
+                    ),
+                };
+                // Input lines count from 1
+                let start = Position::new(hunk.first_line().0 as u32 - 1, 0);
+                let end = Position::new(hunk.last_line().0 as u32 - 1, u32::MAX);
+                diagnostics.push(Diagnostic {
+                    range: Range::new(start, end),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    source: Some("diff-lsp".to_string()),
+                    message,
+                    ..Default::default()
+                });
+            }
+            diagnostics.extend(self.backend_diagnostics(&root, &realigned).await);
+            if realigned.offsets != diff.offsets {
+                if let Some(diff) = self.diff_map.lock().await.get_mut(&diff_uri) {
+                    diff.offsets = realigned.offsets;
+                }
+            }
+            all_diagnostics.insert(diff_uri, diagnostics);
+        }
+
+        let mut published = self.published.lock().await;
+        // Diffs which were closed have their warnings cleared
+        for uri in published.keys().cloned().collect::<Vec<_>>() {
+            all_diagnostics.entry(uri).or_default();
+        }
+        for (uri, diagnostics) in all_diagnostics {
+            if published.get(&uri).unwrap_or(&vec![]) == &diagnostics {
+                continue;
+            }
+            self.client
+                .publish_diagnostics(uri.clone(), diagnostics.clone(), None)
+                .await;
+            if diagnostics.is_empty() {
+                published.remove(&uri);
+            } else {
+                published.insert(uri, diagnostics);
+            }
+        }
+    }
+
+    /// What backends last published for the files of `diff`, moved onto the
+    /// lines showing them.  Diagnostics on lines the diff doesn't show are
+    /// left out.
+    async fn backend_diagnostics(&self, root: &str, diff: &ParsedDiff) -> Vec<Diagnostic> {
+        let mut mapped = vec![];
+        for filename in diff.filenames.iter().unique() {
+            let uri = uri_from_relative_filename(root.to_string(), filename);
+            for backend_mutex in self.running_backends(root, filename).await {
+                let (name, published) = {
+                    let backend = backend_mutex.lock().await;
+                    (backend.lsp_command.clone(), backend.diagnostics())
+                };
+                for diagnostic in published.get(&uri).into_iter().flatten() {
+                    // Source lines count from 1, LSP lines from 0
+                    let to_diff = |line: u32| {
+                        let (input_line, diff_line) =
+                            diff.map_src_line_to_diff(filename, SourceLineNumber(line as u16 + 1))?;
+                        // The text is shown after the line's +, - or space
+                        let marker = (diff_line.line.len() - diff_line.content().len()) as u32;
+                        Some((input_line.0 as u32 - 1, marker))
+                    };
+                    let Some((start_line, marker)) = to_diff(diagnostic.range.start.line) else {
+                        continue;
+                    };
+                    let start =
+                        Position::new(start_line, diagnostic.range.start.character + marker);
+                    let end = match to_diff(diagnostic.range.end.line) {
+                        Some((end_line, marker)) => Position::new(
+                            end_line,
+                            diagnostic.range.end.character.saturating_add(marker),
+                        ),
+                        // The rest of the range isn't in the diff
+                        None => Position::new(start_line, u32::MAX),
+                    };
+                    mapped.push(Diagnostic {
+                        range: Range::new(start, end),
+                        source: diagnostic.source.clone().or(Some(name.clone())),
+                        ..diagnostic.clone()
+                    });
+                }
+            }
+        }
+        mapped
+    }
+
+    /// Brings the files open in backends in line with this session's diffs
+    /// and the disk, or the revision under review.  Each diff holds its files
+    /// open, sending a new version when one changed on disk, and lets go of
+    /// files it no longer has or which are gone from disk.  Backends close a
+    /// file once nothing holds it.
+    async fn sync_backend_documents(&self) {
+        let diffs: Vec<(Url, ParsedDiff)> = {
+            let diff_map = self.diff_map.lock().await;
+            diff_map
+                .iter()
+                .map(|(uri, diff)| (uri.clone(), diff.clone()))
+                .collect()
+        };
+        let mut held = vec![];
+        for (diff_uri, diff) in diffs.iter() {
+            let (root, revision) = self.diff_source(diff_uri, diff).await;
+            for filename in diff.filenames.iter().unique() {
+                held.push((
+                    diff_uri.clone(),
+                    root.clone(),
+                    filename.clone(),
+                    revision.clone(),
+                ));
+            }
+        }
+        let mut texts: HashMap<(Url, Option<String>), Option<String>> = HashMap::new();
+        let mut holding = HashSet::new();
+        for (diff_uri, root, filename, revision) in held {
+            let Some(language) = self.languages.detect(&root, &filename) else {
+                continue;
+            };
+            let uri = uri_from_relative_filename(root.clone(), &filename);
+            let text_key = (uri.clone(), revision.as_ref().map(|(_, r)| r.clone()));
+            if !texts.contains_key(&text_key) {
+                let text = self
+                    .cached_source_text(&root, &filename, revision.as_ref())
+                    .await;
+                texts.insert(text_key.clone(), text);
+            }
+            let Some(text) = texts[&text_key].clone() else {
+                info!("Skipping opening file {:?}", uri.path());
+                continue;
+            };
+            let holder = self.holder(&diff_uri);
+            for backend_mutex in self.get_or_start_backends(&root, &filename).await {
+                let mut backend = backend_mutex.lock().await;
+                backend.open_document(holder.clone(), &uri, &language.name, text.clone());
+            }
+            holding.insert((holder, uri));
+        }
+        for backend_mutex in self.all_backends().await {
+            let mut backend = backend_mutex.lock().await;
+            // Other sessions' holders are theirs to release
+            let released: Vec<(DocumentHolder, Url)> = backend
+                .open_documents()
+                .iter()
+                .flat_map(|(uri, document)| {
+                    document
+                        .holders
+                        .iter()
+                        .filter(|holder| holder.session == self.session)
+                        .map(|holder| (holder.clone(), uri.clone()))
+                })
+                .filter(|held| !holding.contains(held))
+                .collect();
+            for (holder, uri) in released {
+                info!(
+                    "{:?} no longer holds {:?} open in {}",
+                    holder.diff, uri, backend.lsp_command
+                );
+                backend.close_document(&holder, &uri);
+            }
+        }
+    }
+
+    /// The text of a diff: what the editor sent if it's open, which is all
+    /// there is for `untitled:` and other virtual documents, or else the file
+    /// on disk.
+    async fn document_text(&self, uri: &Url) -> Result<String> {
+        if let Some(text) = self.documents.lock().await.get(uri) {
+            return Ok(text.clone());
+        }
+        let path = uri
+            .to_file_path()
+            .map_err(|_| anyhow!("{} isn't open and isn't a file", uri))?;
+        info!("Reading {:?} from disk", path);
+        fs::read_to_string(&path).map_err(|e| anyhow!("Could not read {:?}: {}", path, e))
+    }
+
     async fn refresh_file(&self, uri: &Url) -> Option<ParsedDiff> {
-        let real_path = uri.path();
-        info!("Calling refresh_file for the real path: {:?}", real_path);
+        match self.document_text(uri).await {
+            Ok(contents) => self.parse_diff(uri, &contents).await,
+            Err(e) => {
+                info!("Could not refresh {:?}: {}", uri, e);
+                None
+            }
+        }
+    }
+
+    /// The change checked out at the root of a jj repository, for jj buffers
+    /// which don't say what they're a diff of.
+    async fn jj_change(&self) -> Option<String> {
+        let mut jj_change = self.jj_change.lock().await;
+        if let Some(change) = jj_change.as_ref() {
+            return change.clone();
+        }
+        let root = self.root();
+        let change = tokio::task::spawn_blocking(move || current_revision(&root, Vcs::Jujutsu))
+            .await
+            .ok()
+            .flatten();
+        *jj_change = Some(change.clone());
+        change
+    }
 
-        let contents = fs::read_to_string(real_path).unwrap();
-        if let Some(diff) = ParsedDiff::parse(&contents) {
+    /// Parses `contents` as the diff at `uri`.  When it doesn't parse, say
+    /// halfway through an edit, the last diff which did is kept.
+    async fn parse_diff(&self, uri: &Url, contents: &str) -> Option<ParsedDiff> {
+        let forced_format = self.forced_format.lock().await.clone();
+        if let Some(mut diff) = self.parsers.parse(contents, forced_format.as_deref()) {
+            if diff.vcs == Vcs::Jujutsu && diff.revision().is_none() {
+                if let Some(change_id) = self.jj_change().await {
+                    diff.headers.insert(DiffHeader::ChangeId, change_id);
+                }
+            }
             info!("Inserting diff! 2");
             let mut diff_map = self.diff_map.lock().await;
 
-            if let Some(diff_before) = diff_map.get(&uri) {
+            if let Some(diff_before) = diff_map.get(uri) {
                 info!("Diff before len: {:?}", diff_before.lines_map.len());
             }
             info!("Diff new len: {:?}", diff.lines_map.len());
@@ -198,6 +1369,7 @@ impl DiffLsp {
             diff_map.insert(uri.clone(), diff.clone()); // Use the *same* lock to insert
             Some(diff)
         } else {
+            info!("Could not parse {:?}, keeping the previous diff", uri);
             None
         }
     }
@@ -205,37 +1377,140 @@ impl DiffLsp {
 
 #[tower_lsp::async_trait]
 impl LanguageServer for DiffLsp {
-    async fn initialize(&self, _: InitializeParams) -> LspResult<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> LspResult<InitializeResult> {
         self.client
             .log_message(MessageType::WARNING, "Cruising")
             .await;
         info!("Starting initialize");
-        for backend_mutex in self.backends.values().into_iter() {
-            let mut backend = backend_mutex.lock().await;
-            info!(
-                "Diff LSP doing initialize for backend: {:?}",
-                backend.lsp_command
-            );
-            backend.initialize().unwrap();
+        let can_watch_files = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+            .and_then(|watched| watched.dynamic_registration)
+            .unwrap_or(false);
+        self.can_watch_files
+            .store(can_watch_files, Ordering::Relaxed);
+        let options: InitializationOptions = match params.initialization_options.clone() {
+            Some(options) => serde_json::from_value(options).map_err(|e| {
+                LspError::invalid_params(format!("Invalid initializationOptions: {}", e))
+            })?,
+            None => InitializationOptions::default(),
+        };
+        if let Some(format) = options.format.as_deref() {
+            if self.parsers.find(format).is_none() {
+                return Err(LspError::invalid_params(format!(
+                    "Unknown diff format {:?}, expected one of {:?}",
+                    format,
+                    self.parsers.formats()
+                )));
+            }
+            info!("Forcing diff format: {:?}", format);
+            *self.forced_format.lock().await = Some(format.to_string());
         }
-
-        let res = Ok(InitializeResult {
-            server_info: Some(ServerInfo {
-                name: "diff-lsp".to_string(),
-                version: Some("0.1.0".to_string()),
-            }),
-            capabilities: ServerCapabilities {
-                execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: vec!["custom.notification".to_string()],
-                    ..Default::default()
-                }),
+        self.set_root(&options, &params)?;
+        let root = self.root();
+        info!("Using root {:?}", root);
+        if !root.is_empty() {
+            if let Some(languages) = options.languages {
+                let mut keys: Vec<BackendKey> = languages
+                    .iter()
+                    .map(|language| BackendKey {
+                        language: language.clone(),
+                        root: root.clone(),
+                    })
+                    .collect();
+                // Always locked in the same order, so sessions can't deadlock
+                keys.sort_by(|a, b| a.language.cmp(&b.language));
+                keys.dedup();
+                self.backend_keys.lock().await.extend(keys.clone());
+                let mut _starting = vec![];
+                for key in keys.iter() {
+                    _starting.push(self.starting_lock(key).await.lock_owned().await);
+                }
+                // Another session may already have them running
+                let missing: Vec<String> = {
+                    let backends = self.backends.lock().await;
+                    keys.into_iter()
+                        .filter(|key| !backends.contains_key(key))
+                        .map(|key| key.language)
+                        .collect()
+                };
+                let (backend_root, languages) = (root.clone(), self.languages.clone());
+                let started = tokio::task::spawn_blocking(move || {
+                    create_backends_map(missing, &backend_root, &languages)
+                })
+                .await
+                .map_err(|e| LspError::invalid_params(e.to_string()))?
+                .map_err(|e| LspError::invalid_params(e.to_string()))?;
+                self.backends.lock().await.extend(started);
+            }
+        }
+        self.start_fetch();
+        self.start_periodic_fetch().await;
+        let mut backend_capabilities = vec![];
+        for backend_mutex in self.session_backends().await {
+            let initialized = tokio::task::spawn_blocking(move || {
+                let mut backend = backend_mutex.blocking_lock();
+                info!(
+                    "Diff LSP doing initialize for backend: {:?}",
+                    backend.lsp_command
+                );
+                // Backends shared with another session are already initialized
+                if let Some(capabilities) = backend.capabilities() {
+                    return Some(capabilities.clone());
+                }
+                match backend.initialize() {
+                    Ok(result) => Some(result.capabilities),
+                    Err(e) => {
+                        info!("Failed to initialize {:?}: {:?}", backend.lsp_command, e);
+                        None
+                    }
+                }
+            })
+            .await;
+            if let Ok(Some(capabilities)) = initialized {
+                backend_capabilities.push(capabilities);
+            }
+        }
+        // Backends started once a diff is opened aren't known yet, so with
+        // nothing to go on every request is advertised and checked per file.
+        let mut capabilities = if backend_capabilities.is_empty() {
+            ServerCapabilities {
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
                 definition_provider: Some(OneOf::Left(true)),
                 references_provider: Some(OneOf::Left(true)),
                 type_definition_provider: Some(TypeDefinitionProviderCapability::Simple(true)),
-                // document_symbol_provider: Some(OneOf::Left(true)),
                 ..ServerCapabilities::default()
+            }
+        } else {
+            merge_capabilities(&backend_capabilities)
+        };
+        // Diffs are kept in memory, so edits only need to send what changed
+        capabilities.text_document_sync = Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::INCREMENTAL),
+                ..Default::default()
             },
+        ));
+        capabilities.execute_command_provider = Some(ExecuteCommandOptions {
+            commands: vec![
+                "custom.notification".to_string(),
+                "render".to_string(),
+                "threads".to_string(),
+                "next-thread".to_string(),
+                "previous-thread".to_string(),
+            ],
+            ..Default::default()
+        });
+
+        let res = Ok(InitializeResult {
+            server_info: Some(ServerInfo {
+                name: "diff-lsp".to_string(),
+                version: Some("0.1.0".to_string()),
+            }),
+            capabilities,
             ..Default::default()
         });
         info!("Finished initialize! {:?}", res.clone().unwrap());
@@ -244,7 +1519,7 @@ impl LanguageServer for DiffLsp {
 
     async fn initialized(&self, _: InitializedParams) {
         info!("Starting Initialized");
-        for backend_mutex in self.backends.values().into_iter() {
+        for backend_mutex in self.session_backends().await {
             let mut backend = backend_mutex.lock().await;
             info!(
                 "Diff LSP doing initialized for backend: {:?}",
@@ -252,10 +1527,48 @@ impl LanguageServer for DiffLsp {
             );
             backend.initialized();
         }
+        if self.can_watch_files.load(Ordering::Relaxed) {
+            // So files open in backends are updated when they change on disk
+            let watchers = DidChangeWatchedFilesRegistrationOptions {
+                watchers: vec![FileSystemWatcher {
+                    glob_pattern: GlobPattern::String("**/*".to_string()),
+                    kind: None,
+                }],
+            };
+            let registration = Registration {
+                id: "diff-lsp-watched-files".to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+                register_options: serde_json::to_value(watchers).ok(),
+            };
+            if let Err(e) = self.client.register_capability(vec![registration]).await {
+                info!("Could not watch files: {:?}", e);
+            }
+        }
         info!("Finished all initialized");
     }
 
     async fn shutdown(&self) -> LspResult<()> {
+        if let Some(task) = self.fetch_task.lock().await.take() {
+            task.abort();
+        }
+        // Backends may outlive the session in a daemon, so let go of the
+        // files its diffs had open
+        self.documents.lock().await.clear();
+        self.diff_map.lock().await.clear();
+        self.sync_backend_documents().await;
+        // and of its review worktrees
+        let reviewed: Vec<Url> = self
+            .diff_roots
+            .lock()
+            .await
+            .keys()
+            .filter(|holder| holder.session == self.session)
+            .map(|holder| holder.diff.clone())
+            .collect();
+        for uri in reviewed {
+            self.release_review_worktree(&uri).await;
+        }
+        self.update_diagnostics().await;
         self.client
             .log_message(MessageType::INFO, "Shutting Down.  Cya next time!")
             .await;
@@ -278,6 +1591,9 @@ impl LanguageServer for DiffLsp {
                 .await;
             Ok(None)
         } else if params.command == "refresh" {
+            *self.jj_change.lock().await = None;
+            self.review_revisions.lock().await.clear();
+            self.source_texts.lock().await.clear();
             let keys = {
                 // unlocks when the reference goes out of scope
                 let diff_map = self.diff_map.lock().await;
@@ -286,20 +1602,94 @@ impl LanguageServer for DiffLsp {
             for key in keys {
                 info!("Starting refresh of {:?}", key);
                 self.refresh_file(&key).await;
+                self.open_review_worktree(&key).await;
                 info!("Finished refresh of {:?}", key);
             }
-            fetch_origin_nonblocking(&self.root);
+            self.sync_backend_documents().await;
+            self.update_diagnostics().await;
+            self.start_fetch();
             Ok(None)
         } else if params.command == "fetch" {
-            let mut child = fetch_origin_nonblocking(&self.root);
-            let _ = child.wait().await;
+            // Asked for explicitly, so run even if automatic fetches are off
+            // and report failures as errors
+            run_fetch(&self.client, &self.root(), &self.fetch, &self.fetch_lock)
+                .await
+                .map_err(|e| LspError {
+                    code: ErrorCode::ServerError(1),
+                    message: e.to_string().into(),
+                    data: None,
+                })?;
+            *self.jj_change.lock().await = None;
+            self.review_revisions.lock().await.clear();
             Ok(None)
+        } else if params.command == "render" {
+            // Returns the text a client should display for a diff which isn't
+            // plain text, such as a review saved from GitHub, Gerrit or GitLab.
+            let uri = uri_argument(&params)?;
+            let contents = self
+                .document_text(&uri)
+                .await
+                .map_err(|e| LspError::invalid_params(e.to_string()))?;
+            match ReviewExport::from_json(&contents) {
+                Some(review) => Ok(Some(Value::String(review.render().0))),
+                None => Ok(Some(Value::String(contents))),
+            }
+        } else if params.command == "threads" {
+            // Lists the review threads drawn in a diff, in the order they're in
+            let uri = uri_argument(&params)?;
+            let diff_map = self.diff_map.lock().await;
+            let diff = diff_map.get(&uri).ok_or(LspError::invalid_params(format!(
+                "{} expects an open diff",
+                params.command
+            )))?;
+            let threads = diff
+                .threads
+                .iter()
+                .map(|thread| thread_json(diff, thread))
+                .collect();
+            Ok(Some(Value::Array(threads)))
+        } else if params.command == "next-thread" || params.command == "previous-thread" {
+            // Where the next thread after, or the last before, a line of the
+            // diff is, for jumping between them.  Lines are 0-based.
+            let uri = uri_argument(&params)?;
+            let line = params.arguments.get(1).and_then(|arg| arg.as_u64()).ok_or(
+                LspError::invalid_params(format!(
+                    "{} expects a document uri and a line",
+                    params.command
+                )),
+            )?;
+            let diff_map = self.diff_map.lock().await;
+            let diff = diff_map.get(&uri).ok_or(LspError::invalid_params(format!(
+                "{} expects an open diff",
+                params.command
+            )))?;
+            let mut lines: Vec<u64> = diff
+                .threads
+                .iter()
+                .filter_map(|thread| thread_lines(diff, thread).1)
+                .map(u64::from)
+                .collect();
+            lines.sort();
+            lines.dedup();
+            let found = if params.command == "next-thread" {
+                lines.into_iter().find(|thread_line| *thread_line > line)
+            } else {
+                lines
+                    .into_iter()
+                    .rev()
+                    .find(|thread_line| *thread_line < line)
+            };
+            Ok(found.map(|thread_line| {
+                let position = Position::new(thread_line as u32, 0);
+                serde_json::to_value(Location::new(uri, Range::new(position, position))).unwrap()
+            }))
         } else if params.command == "check" {
             info!("Doing check!");
-            for backend_mutex in self.backends.values().into_iter() {
-                let mut backend = backend_mutex.lock().await;
+            for backend_mutex in self.session_backends().await {
+                let backend = backend_mutex.lock().await;
                 backend.check_messages();
             }
+            self.update_diagnostics().await;
             Ok(None)
         } else {
             Err(LspError::invalid_request())
@@ -325,18 +1715,18 @@ impl LanguageServer for DiffLsp {
         };
 
         info!(
-            "source map: {:?} - {:?}",
-            source_map.source_line, source_map.source_line_text
+            "source map: {:?} (offset {}) - {:?}",
+            source_map.source_line, source_map.offset, source_map.source_line_text
         );
-        let backend_mutex_res = self.get_backend(&source_map);
-        let backend_mutex = match backend_mutex_res {
-            Some(bm) => bm,
-            None => return Err(LspError::new(ErrorCode::ServerError(1))),
-        };
-        let mut backend = backend_mutex.lock().await;
+        let root = self
+            .diff_root(&params.text_document_position_params.text_document.uri)
+            .await;
+        let backends = self
+            .get_backends(&root, &source_map, "textDocument/hover")
+            .await?;
         // TODO do all this mapping in an async func since there's a lot of cloning and whatnot and then futures::join! it with the backend_mutex
         let mut mapped_params = params.clone();
-        let uri = uri_from_relative_filename(self.root.clone(), &source_map.file_name);
+        let uri = uri_from_relative_filename(root, &source_map.file_name);
 
         mapped_params
             .text_document_position_params
@@ -351,60 +1741,82 @@ impl LanguageServer for DiffLsp {
         mapped_params.text_document_position_params.position.line -= 1;
 
         // info!("Hover mapped params: {:?}", mapped_params);
-        let hov_res = backend.hover(mapped_params);
-        // info!("Hover res: {:?}", hov_res);
-        match hov_res {
-            Ok(res) => Ok(res),
-            Err(_) => Err(LspError::new(ErrorCode::ServerError(1))), // Translating LspError type
+        // Hovers can't be merged, so the first backend with one wins
+        let mut failures = 0;
+        for backend_mutex in backends.iter() {
+            let mut backend = backend_mutex.lock().await;
+            match backend.hover(mapped_params.clone()) {
+                Ok(Some(hover)) => return Ok(Some(hover)),
+                Ok(None) => continue,
+                Err(e) => {
+                    backend_failed("textDocument/hover", &backend, e);
+                    failures += 1;
+                }
+            }
         }
+        all_failed("textDocument/hover", failures, backends.len())?;
+        Ok(None)
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        if let Some(diff) = self.refresh_file(&params.text_document.uri).await {
-            let filtered_files: Vec<String> = diff.filenames.clone().into_iter().unique().collect();
-            for filename in filtered_files {
-                let filetype = SupportedFileType::from_filename(filename.clone());
-
-                if let None = filetype {
-                    continue;
-                }
-
-                if let Some(backend_mutex) = self.backends.get(&filetype.unwrap()) {
-                    let mut backend = backend_mutex.lock().await;
-                    let mut these_params = params.clone();
-                    // Here we need to break the LSP contract and use the originator's didOpen URI to read the contents of the file.
-                    these_params.text_document.uri =
-                        uri_from_relative_filename(self.root.clone(), &filename);
-
-                    let full_path = these_params
-                        .text_document
-                        .uri
-                        .as_str()
-                        .replace("file://", "");
-                    info!("Opening filename: {:?}", full_path);
-                    let text_result = fs::read_to_string(&full_path);
-                    match text_result {
-                        Ok(text) => {
-                            these_params.text_document.text = text;
-                            backend.did_open(&these_params);
-                        }
-                        Err(_) => {
-                            info!("Skipping opening file {:?}", full_path);
-                        }
-                    }
-                }
-            }
-        }
+        let uri = params.text_document.uri;
+        self.documents
+            .lock()
+            .await
+            .insert(uri.clone(), params.text_document.text);
+        *self.jj_change.lock().await = None;
+        self.review_revisions.lock().await.remove(&uri);
+        self.source_texts.lock().await.clear();
+        self.refresh_file(&uri).await;
+        self.open_review_worktree(&uri).await;
+        self.sync_backend_documents().await;
+        self.update_diagnostics().await;
         info!("Finished did_open");
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        info!("Calling did_change {:?}", params)
+        info!("Calling did_change {:?}", params.text_document);
+        let uri = params.text_document.uri;
+        let text = {
+            let mut documents = self.documents.lock().await;
+            let Some(text) = documents.get_mut(&uri) else {
+                info!("Ignoring changes to {:?}, which isn't open", uri);
+                return;
+            };
+            for change in params.content_changes.iter() {
+                apply_change(text, change);
+            }
+            text.clone()
+        };
+        self.parse_diff(&uri, &text).await;
+        self.sync_backend_documents().await;
+        self.update_diagnostics().await;
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         info!("Calling did_close {:?}", params);
-        self.refresh_file(&params.text_document.uri).await;
+        let uri = params.text_document.uri;
+        self.documents.lock().await.remove(&uri);
+        self.diff_map.lock().await.remove(&uri);
+        self.review_revisions.lock().await.remove(&uri);
+        self.sync_backend_documents().await;
+        self.release_review_worktree(&uri).await;
+        self.update_diagnostics().await;
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        info!("Calling did_change_watched_files {:?}", params);
+        // This session's backends watch the rest of its projects through us
+        for backend_mutex in self.session_backends().await {
+            let mut backend = backend_mutex.lock().await;
+            backend.notify("workspace/didChangeWatchedFiles".to_string(), &params);
+        }
+        // Only this session's files are updated.  Other sessions' editors
+        // send them their own events, and a file they have open may be from
+        // a revision rather than the disk.
+        self.source_texts.lock().await.clear();
+        self.sync_backend_documents().await;
+        self.update_diagnostics().await;
     }
 
     async fn references(&self, _params: ReferenceParams) -> LspResult<Option<Vec<Location>>> {
@@ -414,26 +1826,38 @@ impl LanguageServer for DiffLsp {
             .await
             .ok_or(LspError::new(ErrorCode::ServerError(1)))?;
 
-        let backend_mutex_res = self.get_backend(&source_map);
-        let backend_mutex = match backend_mutex_res {
-            Some(bm) => bm,
-            None => return Err(LspError::new(ErrorCode::ServerError(1))),
-        };
-
-        let mut backend = backend_mutex.lock().await;
+        let root = self
+            .diff_root(&mapped_params.text_document_position.text_document.uri)
+            .await;
+        let backends = self
+            .get_backends(&root, &source_map, "textDocument/references")
+            .await?;
 
-        let uri = uri_from_relative_filename(self.root.clone(), &source_map.file_name);
+        let uri = uri_from_relative_filename(root, &source_map.file_name);
         mapped_params.text_document_position.text_document.uri = uri;
         mapped_params.text_document_position.position.line = source_map.source_line.0.into();
 
         // Same as for hover
         mapped_params.text_document_position.position.line -= 1;
 
-        let references_result = backend.references(&mapped_params);
-        match references_result {
-            Ok(res) => Ok(res),
-            Err(_) => Err(LspError::new(ErrorCode::ServerError(1))), // Translating LspError type
+        let mut results = vec![];
+        let mut failures = 0;
+        for backend_mutex in backends.iter() {
+            let mut backend = backend_mutex.lock().await;
+            match backend.references(&mapped_params) {
+                Ok(Some(locations)) => results.push(locations),
+                Ok(None) => continue,
+                Err(e) => {
+                    backend_failed("textDocument/references", &backend, e);
+                    failures += 1;
+                }
+            }
+        }
+        all_failed("textDocument/references", failures, backends.len())?;
+        if results.is_empty() {
+            return Ok(None);
         }
+        Ok(Some(merge_locations(results)))
     }
 
     async fn goto_definition(
@@ -446,15 +1870,14 @@ impl LanguageServer for DiffLsp {
             .ok_or(LspError::new(ErrorCode::ServerError(1)))?;
 
         let mut mapped_params = _params.clone();
-        let backend_mutex_res = self.get_backend(&source_map);
-        let backend_mutex = match backend_mutex_res {
-            Some(bm) => bm,
-            None => return Err(LspError::new(ErrorCode::ServerError(1))),
-        };
-
-        let mut backend = backend_mutex.lock().await;
+        let root = self
+            .diff_root(&_params.text_document_position_params.text_document.uri)
+            .await;
+        let backends = self
+            .get_backends(&root, &source_map, "textDocument/definition")
+            .await?;
 
-        let uri = uri_from_relative_filename(self.root.clone(), &source_map.file_name);
+        let uri = uri_from_relative_filename(root, &source_map.file_name);
 
         mapped_params
             .text_document_position_params
@@ -464,11 +1887,21 @@ impl LanguageServer for DiffLsp {
 
         // same as for hover
         mapped_params.text_document_position_params.position.line -= 1;
-        let goto_def_res = backend.goto_definition(&mapped_params);
-        match goto_def_res {
-            Ok(res) => Ok(res),
-            Err(_) => Err(LspError::new(ErrorCode::ServerError(1))), // Translating LspError type
+        let mut results = vec![];
+        let mut failures = 0;
+        for backend_mutex in backends.iter() {
+            let mut backend = backend_mutex.lock().await;
+            match backend.goto_definition(&mapped_params) {
+                Ok(Some(response)) => results.push(response),
+                Ok(None) => continue,
+                Err(e) => {
+                    backend_failed("textDocument/definition", &backend, e);
+                    failures += 1;
+                }
+            }
         }
+        all_failed("textDocument/definition", failures, backends.len())?;
+        Ok(merge_goto_responses(results))
     }
 
     async fn goto_type_definition(
@@ -481,15 +1914,14 @@ impl LanguageServer for DiffLsp {
             .ok_or(LspError::new(ErrorCode::ServerError(1)))?;
 
         let mut mapped_params = params.clone();
-        let backend_mutex_res = self.get_backend(&source_map);
-        let backend_mutex = match backend_mutex_res {
-            Some(bm) => bm,
-            None => return Err(LspError::new(ErrorCode::ServerError(1))),
-        };
-
-        let mut backend = backend_mutex.lock().await;
+        let root = self
+            .diff_root(&params.text_document_position_params.text_document.uri)
+            .await;
+        let backends = self
+            .get_backends(&root, &source_map, "textDocument/typeDefinition")
+            .await?;
 
-        let uri = uri_from_relative_filename(self.root.clone(), &source_map.file_name);
+        let uri = uri_from_relative_filename(root, &source_map.file_name);
 
         mapped_params
             .text_document_position_params
@@ -500,10 +1932,20 @@ impl LanguageServer for DiffLsp {
         // same as for hover
         mapped_params.text_document_position_params.position.line -= 1;
 
-        let goto_type_def_res = backend.goto_type_definition(&mapped_params);
-        match goto_type_def_res {
-            Ok(res) => Ok(res),
-            Err(_) => Err(LspError::new(ErrorCode::ServerError(1))), // Translating LspError type
+        let mut results = vec![];
+        let mut failures = 0;
+        for backend_mutex in backends.iter() {
+            let mut backend = backend_mutex.lock().await;
+            match backend.goto_type_definition(&mapped_params) {
+                Ok(Some(response)) => results.push(response),
+                Ok(None) => continue,
+                Err(e) => {
+                    backend_failed("textDocument/typeDefinition", &backend, e);
+                    failures += 1;
+                }
+            }
         }
+        all_failed("textDocument/typeDefinition", failures, backends.len())?;
+        Ok(merge_goto_responses(results))
     }
 }
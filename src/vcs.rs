@@ -0,0 +1,227 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Result};
+use log::info;
+
+use crate::config::FetchConfig;
+
+/// The version control system a diff or project root belongs to.
+#[derive(Debug, Default, Hash, PartialEq, Eq, Copy, Clone)]
+pub enum Vcs {
+    #[default]
+    Git,
+    Jujutsu,
+    Mercurial,
+    Sapling,
+}
+
+impl Vcs {
+    /// The directory at a repository root which marks it as this VCS.
+    pub fn marker(&self) -> &'static str {
+        match self {
+            Vcs::Git => ".git",
+            Vcs::Jujutsu => ".jj",
+            Vcs::Mercurial => ".hg",
+            Vcs::Sapling => ".sl",
+        }
+    }
+
+    /// The command line tool for this VCS.
+    pub fn program(&self) -> &'static str {
+        match self {
+            Vcs::Git => "git",
+            Vcs::Jujutsu => "jj",
+            Vcs::Mercurial => "hg",
+            Vcs::Sapling => "sl",
+        }
+    }
+
+    /// Arguments which update the local copy of the remote without touching
+    /// the working copy.  Refspecs become branches for jj and revisions for
+    /// hg and sl.
+    pub fn fetch_args(&self, remote: Option<&str>, refspecs: &[String]) -> Vec<String> {
+        let mut args: Vec<String> = match self {
+            Vcs::Git => vec!["fetch".to_string(), remote.unwrap_or("origin").to_string()],
+            Vcs::Jujutsu => vec!["git".to_string(), "fetch".to_string()],
+            Vcs::Mercurial | Vcs::Sapling => vec!["pull".to_string()],
+        };
+        for refspec in refspecs {
+            match self {
+                Vcs::Git => (),
+                Vcs::Jujutsu => args.push("--branch".to_string()),
+                Vcs::Mercurial | Vcs::Sapling => args.push("-r".to_string()),
+            }
+            args.push(refspec.clone());
+        }
+        match (self, remote) {
+            (Vcs::Jujutsu, Some(remote)) => {
+                args.extend(["--remote".to_string(), remote.to_string()])
+            }
+            (Vcs::Mercurial | Vcs::Sapling, Some(remote)) => args.push(remote.to_string()),
+            _ => (),
+        }
+        args
+    }
+
+    /// Checks `dir` itself (not its parents) for a repository marker.
+    pub fn detect(dir: &Path) -> Option<Vcs> {
+        // jj repos are usually colocated with a .git folder, so check for jj first.
+        [Vcs::Jujutsu, Vcs::Sapling, Vcs::Mercurial, Vcs::Git]
+            .into_iter()
+            .find(|vcs| dir.join(vcs.marker()).exists())
+    }
+}
+
+/// Walks up from `start` to the nearest repository root.
+pub fn find_repo_root(start: &Path) -> Option<(PathBuf, Vcs)> {
+    start
+        .ancestors()
+        .find_map(|dir| Vcs::detect(dir).map(|vcs| (dir.to_path_buf(), vcs)))
+}
+
+/// Asks the VCS for the revision currently checked out at `root`.  For jj this
+/// is the change id of the working copy, which is stable across rewrites.
+pub fn current_revision(root: &str, vcs: Vcs) -> Option<String> {
+    let args: &[&str] = match vcs {
+        Vcs::Git => &["rev-parse", "HEAD"],
+        Vcs::Jujutsu => &["log", "--no-graph", "-r", "@", "-T", "change_id"],
+        Vcs::Mercurial | Vcs::Sapling => &["log", "-r", ".", "-T", "{node}"],
+    };
+    match Command::new(vcs.program())
+        .args(args)
+        .current_dir(root)
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            let revision = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if revision.is_empty() {
+                None
+            } else {
+                Some(revision)
+            }
+        }
+        Ok(output) => {
+            info!(
+                "Failed to get revision for {:?}: {}",
+                root,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            None
+        }
+        Err(e) => {
+            info!("Failed to run {:?} for {:?}: {}", vcs, root, e);
+            None
+        }
+    }
+}
+
+pub(crate) fn run(root: &str, vcs: Vcs, args: &[&str]) -> Result<String> {
+    let output = Command::new(vcs.program())
+        .args(args)
+        .current_dir(root)
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} {} failed: {}",
+            vcs.program(),
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Resolves `revision` to a commit the VCS at `root` knows.  Git branches
+/// which were only fetched are looked for under `remote` too.  jj, hg and sl
+/// take their revisions as they are.
+pub fn resolve_revision(root: &str, vcs: Vcs, revision: &str, remote: &str) -> Option<String> {
+    if vcs != Vcs::Git {
+        return Some(revision.to_string());
+    }
+    [revision.to_string(), format!("{}/{}", remote, revision)]
+        .iter()
+        .find_map(|candidate| {
+            let spec = format!("{}^{{commit}}", candidate);
+            run(root, vcs, &["rev-parse", "--verify", "--quiet", &spec]).ok()
+        })
+        .map(|commit| commit.trim().to_string())
+}
+
+/// Whether `revision` is what's checked out at `root`, in which case the
+/// working copy, uncommitted changes and all, is what a diff of it shows.
+pub fn is_checked_out(root: &str, vcs: Vcs, revision: &str) -> bool {
+    // Short and full ids of the same revision are both accepted
+    current_revision(root, vcs)
+        .is_some_and(|current| current.starts_with(revision) || revision.starts_with(&current))
+}
+
+/// The contents of `path`, relative to `root`, at `revision`.
+pub fn show_file(root: &str, vcs: Vcs, revision: &str, path: &str) -> Result<String> {
+    let path = path.trim_start_matches('/');
+    match vcs {
+        // ./ makes the path relative to root rather than the top of the repo
+        Vcs::Git => run(root, vcs, &["show", &format!("{}:./{}", revision, path)]),
+        Vcs::Jujutsu => run(root, vcs, &["file", "show", "-r", revision, path]),
+        Vcs::Mercurial | Vcs::Sapling => run(root, vcs, &["cat", "-r", revision, path]),
+    }
+}
+
+/// Whether the repository at `repo_path` has any remotes to fetch from.  If
+/// they can't be listed the fetch is left to report why.
+async fn has_remotes(repo_path: &str, vcs: Vcs) -> bool {
+    let args: &[&str] = match vcs {
+        Vcs::Git => &["remote"],
+        Vcs::Jujutsu => &["git", "remote", "list"],
+        Vcs::Mercurial | Vcs::Sapling => &["paths"],
+    };
+    match tokio::process::Command::new(vcs.program())
+        .current_dir(repo_path)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => !output.stdout.trim_ascii().is_empty(),
+        _ => true,
+    }
+}
+
+/// Fetches from the remote of whichever VCS manages `repo_path`, returning the
+/// command which was run.  Returns None without running anything if
+/// `repo_path` isn't in a repository or the repository has no remotes.
+pub async fn fetch(repo_path: &str, config: &FetchConfig) -> Result<Option<String>> {
+    if repo_path.is_empty() {
+        return Err(anyhow!("No root to fetch in"));
+    }
+    let vcs = match find_repo_root(Path::new(repo_path)) {
+        Some((_, vcs)) => vcs,
+        None => {
+            info!("Not fetching, {:?} is not in a repository", repo_path);
+            return Ok(None);
+        }
+    };
+    if !has_remotes(repo_path, vcs).await {
+        info!("Not fetching, {:?} has no remotes", repo_path);
+        return Ok(None);
+    }
+    let args = vcs.fetch_args(config.remote.as_deref(), &config.refspecs);
+    let command = format!("{} {}", vcs.program(), args.join(" "));
+    info!("Running {:?} in {:?}", command, repo_path);
+    // Output is captured, as stdout may be the LSP connection
+    let output = tokio::process::Command::new(vcs.program())
+        .current_dir(repo_path)
+        .args(&args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| anyhow!("Failed to run {}: {}", command, e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(Some(command))
+}
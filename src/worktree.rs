@@ -0,0 +1,161 @@
+use std::cmp::Reverse;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use expanduser::expanduser;
+use log::info;
+
+use crate::config::WorktreeConfig;
+use crate::vcs::{run, Vcs};
+
+/// Where review worktrees are made: the configured `dir`, or diff-lsp's
+/// folder in `$XDG_CACHE_HOME`, falling back to `~/.cache`.
+pub fn worktrees_dir(config: &WorktreeConfig) -> PathBuf {
+    if let Some(dir) = config.dir.as_deref() {
+        if let Ok(dir) = expanduser(dir) {
+            return dir;
+        }
+    }
+    match std::env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("diff-lsp/worktrees"),
+        _ => expanduser("~/.cache/diff-lsp/worktrees").unwrap(),
+    }
+}
+
+/// The review worktree in `dir` for `head` of the repository at `repo`, e.g.
+/// `diff-lsp-1a2b3c4d-feature-x` for the branch `feature/x`.  The hash of the
+/// repository's path keeps same-named repositories apart.
+pub fn worktree_path(dir: &Path, repo: &Path, head: &str) -> PathBuf {
+    let repo = repo.canonicalize().unwrap_or_else(|_| repo.to_path_buf());
+    let repo_name = repo
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let head: String = head
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '-',
+        })
+        .collect();
+    dir.join(format!("{}-{:08x}-{}", repo_name, path_hash(&repo), head))
+}
+
+/// FNV-1a of `path`, which unlike std's hasher stays the same across builds.
+fn path_hash(path: &Path) -> u32 {
+    path.to_string_lossy()
+        .bytes()
+        .fold(0x811c9dc5, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        })
+}
+
+fn utf8(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow!("{:?} isn't a valid path", path))
+}
+
+/// Checks `commit` out in the review worktree for `head`, adding the worktree
+/// to the git repository at `repo` if it isn't there yet.
+pub fn ensure_review_worktree(
+    repo: &Path,
+    head: &str,
+    commit: &str,
+    config: &WorktreeConfig,
+) -> Result<PathBuf> {
+    let dir = worktrees_dir(config);
+    fs::create_dir_all(&dir)?;
+    let path = worktree_path(&fs::canonicalize(&dir)?, repo, head);
+    if path.join(".git").exists() {
+        // Pushed to since it was made
+        run(
+            utf8(&path)?,
+            Vcs::Git,
+            &["checkout", "--quiet", "--detach", commit],
+        )?;
+    } else {
+        info!("Adding a worktree for {} at {:?}", head, path);
+        run(
+            utf8(repo)?,
+            Vcs::Git,
+            &[
+                "worktree",
+                "add",
+                "--quiet",
+                "--detach",
+                utf8(&path)?,
+                commit,
+            ],
+        )?;
+    }
+    // The folder's modification time says when it was last used
+    File::open(&path)?.set_modified(SystemTime::now())?;
+    Ok(path)
+}
+
+/// The review worktrees of `repo`, most recently used first.
+pub fn review_worktrees(repo: &Path, config: &WorktreeConfig) -> Result<Vec<PathBuf>> {
+    let Ok(dir) = fs::canonicalize(worktrees_dir(config)) else {
+        return Ok(vec![]);
+    };
+    let listing = run(utf8(repo)?, Vcs::Git, &["worktree", "list", "--porcelain"])?;
+    let mut worktrees: Vec<(SystemTime, PathBuf)> = listing
+        .lines()
+        .filter_map(|line| line.strip_prefix("worktree "))
+        .map(PathBuf::from)
+        .filter(|path| path.starts_with(&dir))
+        .filter_map(|path| Some((fs::metadata(&path).ok()?.modified().ok()?, path)))
+        .collect();
+    worktrees.sort_by_key(|(modified, _)| Reverse(*modified));
+    Ok(worktrees.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Removes the review worktree at `path` from `repo`, changes and all.
+pub fn remove_review_worktree(repo: &Path, path: &Path) -> Result<()> {
+    info!("Removing the review worktree at {:?}", path);
+    run(
+        utf8(repo)?,
+        Vcs::Git,
+        &["worktree", "remove", "--force", utf8(path)?],
+    )?;
+    Ok(())
+}
+
+/// Removes the least recently used review worktrees of `repo` beyond
+/// `config.max`, leaving any which `in_use` paths are inside.
+pub fn prune_review_worktrees(
+    repo: &Path,
+    config: &WorktreeConfig,
+    in_use: &[PathBuf],
+) -> Result<Vec<PathBuf>> {
+    let mut removed = vec![];
+    for path in review_worktrees(repo, config)?.into_iter().skip(config.max) {
+        if in_use.iter().any(|used| used.starts_with(&path)) {
+            continue;
+        }
+        remove_review_worktree(repo, &path)?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// Removes the review worktree of `repo` which `closed` is inside, once no
+/// `in_use` path is inside it any more.
+pub fn close_review_worktree(
+    repo: &Path,
+    config: &WorktreeConfig,
+    closed: &Path,
+    in_use: &[PathBuf],
+) -> Result<Vec<PathBuf>> {
+    let mut removed = vec![];
+    for path in review_worktrees(repo, config)? {
+        if !closed.starts_with(&path) || in_use.iter().any(|used| used.starts_with(&path)) {
+            continue;
+        }
+        remove_review_worktree(repo, &path)?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
@@ -0,0 +1,67 @@
+// Each test crate uses only some of these
+#![allow(dead_code)]
+
+use diff_lsp::client::OpenDocument;
+use diff_lsp::config::LanguageRegistry;
+use diff_lsp::server::{DiffLsp, SharedBackends};
+use std::collections::HashMap;
+use std::path::Path;
+use tower_lsp::lsp_types::*;
+use tower_lsp::{ClientSocket, LanguageServer, LspService};
+
+/// Rust files, with cat standing in for rust-analyzer, swallowing the
+/// notifications.
+pub fn cat_languages() -> LanguageRegistry {
+    LanguageRegistry::from_toml("[language.rust]\ncommand = \"cat\"").unwrap()
+}
+
+/// A session at `root` with cat backends.
+pub fn cat_server(root: &Path) -> (LspService<DiffLsp>, ClientSocket) {
+    cat_session(root, SharedBackends::default(), |_| ())
+}
+
+/// A session at `root` with cat backends in `backends`, which other sessions
+/// may be using too, set up further by `configure`.
+pub fn cat_session(
+    root: &Path,
+    backends: SharedBackends,
+    configure: impl FnOnce(&mut DiffLsp),
+) -> (LspService<DiffLsp>, ClientSocket) {
+    LspService::new(|client| {
+        let mut server = DiffLsp::with_shared_backends(
+            client,
+            backends,
+            root.display().to_string(),
+            cat_languages(),
+        );
+        configure(&mut server);
+        server
+    })
+}
+
+/// Opens `text` as the diff at `uri`.
+pub async fn open_diff(server: &DiffLsp, uri: &Url, text: &str) {
+    server
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "diff".to_string(),
+                version: 1,
+                text: text.to_string(),
+            },
+        })
+        .await;
+}
+
+/// The documents the first backend `server` started has open.
+pub async fn open_documents(server: &DiffLsp) -> HashMap<Url, OpenDocument> {
+    let backends = server.backends.lock().await;
+    let (_, backend) = &backends.values().next().unwrap()[0];
+    let documents = backend.lock().await.open_documents().clone();
+    documents
+}
+
+/// The text the first backend `server` started has open for `uri`.
+pub async fn open_text(server: &DiffLsp, uri: &Url) -> String {
+    open_documents(server).await[uri].text.clone()
+}
@@ -0,0 +1,44 @@
+#!/bin/sh
+# A stand-in language server for tests.  It answers initialize, then either
+# fails every request (`fail`) or answers hovers and references (`answer`).
+# `diagnose` answers too, and publishes an error on line 2 of opened files.
+mode="$1"
+while IFS= read -r header; do
+    length=$(printf '%s' "$header" | tr -d '\r' | sed -n 's/^Content-Length: //p')
+    [ -z "$length" ] && continue
+    IFS= read -r _
+    body=$(dd bs=1 count="$length" 2>/dev/null)
+    case "$mode:$body" in
+    'diagnose:'*'"method":"textDocument/didOpen"'*)
+        # Its text comes before its uri, so take the last uri
+        uri=$(printf '%s' "$body" | sed -n 's/.*"uri":"\([^"]*\)".*/\1/p')
+        message="{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{\"uri\":\"$uri\",\"diagnostics\":[{\"range\":{\"start\":{\"line\":1,\"character\":3},\"end\":{\"line\":1,\"character\":6}},\"severity\":1,\"message\":\"fake error\"}]}}"
+        printf 'Content-Length: %s\r\n\r\n%s' "${#message}" "$message"
+        continue
+        ;;
+    esac
+    # Notifications have no id and get no answer
+    id=$(printf '%s' "$body" | sed -n 's/^{"id":\([0-9]*\).*/\1/p')
+    [ -z "$id" ] && continue
+    case "$body" in
+    *'"method":"initialize"'*)
+        reply='"result":{"capabilities":{"hoverProvider":true,"referencesProvider":true}}'
+        ;;
+    *)
+        if [ "$mode" = fail ]; then
+            reply='"error":{"code":-32603,"message":"fake failure"}'
+        else
+            case "$body" in
+            *'"method":"textDocument/hover"'*)
+                reply='"result":{"contents":"fake hover"}'
+                ;;
+            *)
+                reply='"result":[{"uri":"file:///fake.rs","range":{"start":{"line":1,"character":0},"end":{"line":1,"character":4}}}]'
+                ;;
+            esac
+        fi
+        ;;
+    esac
+    message="{\"jsonrpc\":\"2.0\",\"id\":$id,$reply}"
+    printf 'Content-Length: %s\r\n\r\n%s' "${#message}" "$message"
+done
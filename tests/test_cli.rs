@@ -0,0 +1,65 @@
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use diff_lsp::cli::{Cli, Command};
+    use log::LevelFilter;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_server_flags() {
+        let cli = Cli::try_parse_from([
+            "diff-lsp",
+            "--root",
+            "~/src/monorepo",
+            "--worktree",
+            "services/api",
+            "--lang",
+            "go,rust",
+            "--lang",
+            "python",
+            "--log-level",
+            "debug",
+            "--no-fetch",
+        ])
+        .unwrap();
+        assert_eq!(cli.root, Some("~/src/monorepo".to_string()));
+        assert_eq!(cli.worktree, Some("services/api".to_string()));
+        assert_eq!(cli.languages, vec!["go", "rust", "python"]);
+        assert_eq!(cli.log_level, LevelFilter::Debug);
+        assert!(cli.no_fetch);
+        assert!(cli.command.is_none());
+        assert!(cli.listen.is_none());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let cli = Cli::try_parse_from(["diff-lsp"]).unwrap();
+        assert!(cli.root.is_none());
+        assert!(cli.init_file.is_none());
+        assert_eq!(cli.log_file, "~/.diff-lsp.log".to_string());
+        assert_eq!(cli.log_level, LevelFilter::Info);
+        assert!(!cli.no_fetch);
+    }
+
+    #[test]
+    fn test_subcommands() {
+        let cli = Cli::try_parse_from(["diff-lsp", "--config", "ci.toml", "doctor"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Doctor)));
+        assert_eq!(cli.config, Some(PathBuf::from("ci.toml")));
+
+        let cli = Cli::try_parse_from(["diff-lsp", "render", "pr.json"]).unwrap();
+        match cli.command {
+            Some(Command::Render { file }) => assert_eq!(file, PathBuf::from("pr.json")),
+            _ => panic!("expected render"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_flags() {
+        assert!(Cli::try_parse_from(["diff-lsp", "--log-level", "loud"]).is_err());
+        assert!(
+            Cli::try_parse_from(["diff-lsp", "--stdio", "--listen", "127.0.0.1:9257"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["diff-lsp", "--tempfile", "/tmp/diff_lsp_1"]).is_err());
+    }
+}
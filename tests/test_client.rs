@@ -0,0 +1,157 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::client::{
+        client_capabilities, supports_method, workspace_folder, ClientForBackendServer,
+        DocumentHolder, OpenDocument,
+    };
+    use diff_lsp::config::BackendConfig;
+    use std::collections::HashSet;
+    use std::path::Path;
+    use tower_lsp::lsp_types::*;
+
+    #[test]
+    fn test_client_capabilities() {
+        let capabilities = client_capabilities();
+        let workspace = capabilities.workspace.unwrap();
+        assert_eq!(workspace.workspace_folders, Some(true));
+
+        let text_document = capabilities.text_document.unwrap();
+        assert_eq!(
+            text_document.hover.unwrap().content_format,
+            Some(vec![MarkupKind::Markdown, MarkupKind::PlainText])
+        );
+        assert_eq!(text_document.definition.unwrap().link_support, Some(true));
+        assert!(text_document.references.is_some());
+        // Diagnostics are mapped onto the diff and published with its own
+        assert!(text_document.publish_diagnostics.is_some());
+    }
+
+    #[test]
+    fn test_workspace_folders() {
+        let folder = workspace_folder(Path::new("/home/chris/monorepo/services/api")).unwrap();
+        assert_eq!(folder.name, "api".to_string());
+        assert_eq!(
+            folder.uri.as_str(),
+            "file:///home/chris/monorepo/services/api/"
+        );
+
+        // cat stands in for a backend, swallowing the notifications
+        let config = BackendConfig {
+            command: "cat".to_string(),
+            ..Default::default()
+        };
+        let dir = std::env::current_dir().unwrap();
+        let mut client = ClientForBackendServer::new(&config, dir.to_str().unwrap()).unwrap();
+        assert_eq!(client.workspace_folders().len(), 1);
+        // Not initialized, so nothing is known about its capabilities
+        assert!(!client.supports_workspace_folders());
+        assert!(client.capabilities().is_none());
+        assert!(client.supports("textDocument/hover"));
+
+        client.add_workspace_folder(&dir.join("tests"));
+        client.add_workspace_folder(&dir.join("tests"));
+        assert_eq!(client.workspace_folders().len(), 2);
+        assert_eq!(client.workspace_folders()[1].name, "tests".to_string());
+    }
+
+    #[test]
+    fn test_supports_method() {
+        let capabilities = ServerCapabilities {
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            definition_provider: Some(OneOf::Right(DefinitionOptions {
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+            })),
+            references_provider: Some(OneOf::Left(false)),
+            ..Default::default()
+        };
+        assert!(supports_method(&capabilities, "textDocument/hover"));
+        assert!(supports_method(&capabilities, "textDocument/definition"));
+        assert!(!supports_method(&capabilities, "textDocument/references"));
+        assert!(!supports_method(
+            &capabilities,
+            "textDocument/typeDefinition"
+        ));
+        // Notifications aren't checked
+        assert!(supports_method(&capabilities, "textDocument/didOpen"));
+    }
+
+    #[test]
+    fn test_document_holders() {
+        let config = BackendConfig {
+            command: "cat".to_string(),
+            ..Default::default()
+        };
+        let dir = std::env::current_dir().unwrap();
+        let mut client = ClientForBackendServer::new(&config, dir.to_str().unwrap()).unwrap();
+        let uri = Url::from_file_path(dir.join("src/lib.rs")).unwrap();
+        let holder = |session: u64, diff: &str| DocumentHolder {
+            session,
+            diff: Url::parse(diff).unwrap(),
+        };
+        let (status, review) = (holder(1, "untitled:status"), holder(2, "untitled:review"));
+
+        client.open_document(status.clone(), &uri, "rust", "fn main() {}".to_string());
+        assert_eq!(client.open_documents()[&uri].version, 1);
+        // A second diff with the same file isn't opened again
+        client.open_document(review.clone(), &uri, "rust", "fn main() {}".to_string());
+        assert_eq!(client.open_documents()[&uri].version, 1);
+        client.open_document(
+            review.clone(),
+            &uri,
+            "rust",
+            "fn main() { run() }".to_string(),
+        );
+        assert_eq!(
+            client.open_documents()[&uri],
+            OpenDocument {
+                version: 2,
+                text: "fn main() { run() }".to_string(),
+                holders: HashSet::from([status.clone(), review.clone()]),
+            }
+        );
+        // Only open files are updated
+        client.update_document(
+            &Url::from_file_path(dir.join("src/main.rs")).unwrap(),
+            "".to_string(),
+        );
+        assert_eq!(client.open_documents().len(), 1);
+
+        client.close_document(&status, &uri);
+        assert_eq!(
+            client.open_documents()[&uri].holders,
+            HashSet::from([review.clone()])
+        );
+        client.close_document(&review, &uri);
+        assert!(client.open_documents().is_empty());
+        client.close_document(&review, &uri);
+
+        // Reopening starts from the first version again
+        client.open_document(status, &uri, "rust", "fn main() {}".to_string());
+        assert_eq!(client.open_documents()[&uri].version, 1);
+    }
+
+    #[test]
+    fn test_stopped_backend() {
+        // true exits straight away, as a crashed backend would
+        let config = BackendConfig {
+            command: "true".to_string(),
+            ..Default::default()
+        };
+        let dir = std::env::current_dir().unwrap();
+        let mut client = ClientForBackendServer::new(&config, dir.to_str().unwrap()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let uri = Url::from_file_path(dir.join("src/main.rs")).unwrap();
+        // Notifications are dropped, requests fail
+        client.did_close(&DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+        });
+        let hover = client.hover(HoverParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position: Position::new(0, 0),
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+        assert!(hover.is_err());
+    }
+}
@@ -0,0 +1,179 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::config::LanguageRegistry;
+    use serde_json::json;
+    use std::fs;
+    use std::path::Path;
+
+    const CONFIG: &str = r#"
+[language.rust]
+command = "/opt/rust-analyzer/2024-05-06/rust-analyzer"
+
+[language.cpp]
+extensions = ["c", "h", "cc", "cpp"]
+command = "clangd"
+args = ["--background-index", "--clang-tidy"]
+env = { CLANGD_LOG = "error" }
+
+[language.java]
+extensions = ["java"]
+filenames = ["pom.xml"]
+globs = ["**/gradle/*.gradle"]
+command = "jdtls"
+initialization_options = { bundles = [] }
+
+[language.java.settings.java.format]
+enabled = false
+"#;
+
+    #[test]
+    fn test_default_languages() {
+        let languages = LanguageRegistry::default();
+        let typescript = languages.language_for("src/app.tsx").unwrap();
+        assert_eq!(typescript.name, "typescript".to_string());
+        assert_eq!(
+            typescript.backends[0].command,
+            "typescript-language-server".to_string()
+        );
+        assert_eq!(typescript.backends[0].args, vec!["--stdio".to_string()]);
+        assert_eq!(languages.get("cpp").unwrap().backends[0].command, "clangd");
+        assert!(languages.get("perl").is_none());
+    }
+
+    #[test]
+    fn test_config_overrides_defaults() {
+        let languages = LanguageRegistry::from_toml(CONFIG).unwrap();
+        let rust = languages.get("rust").unwrap();
+        assert_eq!(
+            rust.backends[0].command,
+            "/opt/rust-analyzer/2024-05-06/rust-analyzer".to_string()
+        );
+        // Only what the config mentions changes
+        assert_eq!(rust.extensions, vec!["rs".to_string()]);
+        assert_eq!(languages.get("go").unwrap().backends[0].command, "gopls");
+    }
+
+    #[test]
+    fn test_config_adds_languages() {
+        let languages = LanguageRegistry::from_toml(CONFIG).unwrap();
+
+        let cpp = languages.language_for("src/parser.h").unwrap();
+        assert_eq!(cpp.name, "cpp".to_string());
+        assert_eq!(cpp.backends[0].args.len(), 2);
+        assert_eq!(
+            cpp.backends[0].env.get("CLANGD_LOG"),
+            Some(&"error".to_string())
+        );
+
+        let java = languages.get("java").unwrap();
+        assert_eq!(
+            java.backends[0].initialization_options,
+            Some(json!({"bundles": []}))
+        );
+        assert_eq!(
+            java.backends[0].settings,
+            Some(json!({"java": {"format": {"enabled": false}}}))
+        );
+        assert_eq!(languages.language_for("pom.xml").unwrap().name, "java");
+        assert_eq!(
+            languages
+                .language_for("app/gradle/build.gradle")
+                .unwrap()
+                .name,
+            "java"
+        );
+        assert!(languages.language_for("build.gradle").is_none());
+    }
+
+    #[test]
+    fn test_multiple_backends() {
+        let languages = LanguageRegistry::from_toml(
+            r#"
+[[language.python.backends]]
+command = "pyright-langserver"
+args = ["--stdio"]
+methods = ["textDocument/hover", "textDocument/definition"]
+
+[[language.python.backends]]
+name = "ruff"
+command = "ruff-lsp"
+"#,
+        )
+        .unwrap();
+        let python = languages.get("python").unwrap();
+        // backends replaces the built-in pylsp
+        assert_eq!(python.backends.len(), 2);
+        assert_eq!(python.backends[0].name, "pyright-langserver".to_string());
+        assert_eq!(python.backends[1].name, "ruff".to_string());
+
+        assert!(python.backends[0].handles("textDocument/hover"));
+        assert!(!python.backends[0].handles("textDocument/references"));
+        // No methods means every method
+        assert!(python.backends[1].handles("textDocument/references"));
+
+        // Backends can be inline tables too
+        let languages = LanguageRegistry::from_toml(
+            r#"
+[language.typescript]
+backends = [
+    { command = "typescript-language-server", args = ["--stdio"] },
+    { name = "eslint", command = "vscode-eslint-language-server", args = ["--stdio"] },
+]
+"#,
+        )
+        .unwrap();
+        let typescript = languages.get("typescript").unwrap();
+        assert_eq!(typescript.backends[1].name, "eslint".to_string());
+
+        assert!(LanguageRegistry::from_toml(
+            "[language.python]\ncommand = \"pylsp\"\nbackends = [{ command = \"ruff-lsp\" }]"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_project_root() {
+        let workspace = std::env::temp_dir().join(format!("diff_lsp_roots_{}", std::process::id()));
+        fs::create_dir_all(workspace.join("services/api/handlers")).unwrap();
+        fs::create_dir_all(workspace.join("web/src")).unwrap();
+        fs::create_dir_all(workspace.join("tools")).unwrap();
+        fs::write(workspace.join("services/api/go.mod"), "module api\n").unwrap();
+        fs::write(workspace.join("web/tsconfig.json"), "{}\n").unwrap();
+        let workspace_root = workspace.display().to_string();
+
+        let languages = LanguageRegistry::default();
+        let go = languages.get("go").unwrap();
+        assert_eq!(
+            go.project_root(&workspace_root, "services/api/handlers/user.go"),
+            workspace.join("services/api").display().to_string()
+        );
+        // Files outside any project use the workspace
+        assert_eq!(
+            go.project_root(&workspace_root, "tools/gen.go"),
+            workspace_root
+        );
+        let typescript = languages.get("typescript").unwrap();
+        assert_eq!(
+            typescript.project_root(&workspace_root, "/web/src/app.ts"),
+            workspace.join("web").display().to_string()
+        );
+        // A marker at the workspace root is the workspace
+        fs::write(workspace.join("go.mod"), "module root\n").unwrap();
+        assert_eq!(
+            go.project_root(&workspace_root, "tools/gen.go"),
+            workspace_root
+        );
+
+        fs::remove_dir_all(&workspace).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        // New languages need a command
+        assert!(LanguageRegistry::from_toml("[language.perl]\nextensions = [\"pl\"]").is_err());
+        // Typos aren't silently ignored
+        assert!(LanguageRegistry::from_toml("[language.rust]\ncomand = \"ra\"").is_err());
+        assert!(LanguageRegistry::from_toml("[language.rust]\nglobs = [\"[\"]").is_err());
+        assert!(LanguageRegistry::load(Some(Path::new("tests/data/missing.toml"))).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::config::LanguageRegistry;
+    use diff_lsp::daemon::{default_socket_path, Daemon, DaemonAddress};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+    use tower_lsp::lsp_types::InitializeParams;
+    use tower_lsp::{LanguageServer, LspService};
+
+    #[test]
+    fn test_daemon_address() {
+        assert_eq!(
+            DaemonAddress::new(None, None),
+            DaemonAddress::Unix(default_socket_path())
+        );
+        assert_eq!(
+            DaemonAddress::new(Some(PathBuf::from("/run/user/1000/review.sock")), None),
+            DaemonAddress::Unix(PathBuf::from("/run/user/1000/review.sock"))
+        );
+        assert_eq!(
+            DaemonAddress::new(None, Some("127.0.0.1:9257".to_string())),
+            DaemonAddress::Tcp("127.0.0.1:9257".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sessions_share_backends() {
+        let daemon = Daemon::new(LanguageRegistry::default());
+        let (first, _socket) = LspService::new(|client| daemon.session(client));
+        let (second, _socket) = LspService::new(|client| daemon.session(client));
+        assert!(Arc::ptr_eq(
+            &first.inner().backends,
+            &second.inner().backends
+        ));
+        assert!(Arc::ptr_eq(
+            &first.inner().starting,
+            &second.inner().starting
+        ));
+        // Each session has its own root, set by its initialize
+        assert_eq!(first.inner().root(), String::new());
+    }
+
+    async fn initialize(path: &PathBuf) -> String {
+        let mut stream = UnixStream::connect(path).await.unwrap();
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#;
+        let message = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        stream.write_all(message.as_bytes()).await.unwrap();
+        let mut received = String::new();
+        let mut buffer = [0; 4096];
+        while !received.contains("\"id\":1") {
+            let read = stream.read(&mut buffer).await.unwrap();
+            assert!(read > 0, "daemon closed the connection");
+            received.push_str(&String::from_utf8_lossy(&buffer[..read]));
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn test_daemon_serves_several_sessions() {
+        let path =
+            std::env::temp_dir().join(format!("diff_lsp_daemon_{}.sock", std::process::id()));
+        let address = DaemonAddress::Unix(path.clone());
+        let mut daemon = Daemon::new(LanguageRegistry::default());
+        daemon.fetch.enabled = false;
+        let running = daemon.clone();
+        let listening = address.clone();
+        tokio::spawn(async move { running.run(&listening).await });
+        while UnixStream::connect(&path).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Two editors at once, each getting its own session
+        let first = initialize(&path).await;
+        let second = initialize(&path).await;
+        assert!(first.contains("\"capabilities\""));
+        assert!(second.contains("\"capabilities\""));
+
+        // Only one daemon per socket
+        assert!(daemon.run(&address).await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sessions_start_backends_once() {
+        let dir = std::env::temp_dir().join(format!("diff_lsp_race_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("started");
+        // Notes each start, then swallows the notifications as cat does
+        let languages = LanguageRegistry::from_toml(&format!(
+            "[language.rust]\ncommand = \"sh\"\nargs = [\"-c\", \"echo started >> {}; exec cat\"]",
+            log.display()
+        ))
+        .unwrap();
+        let mut daemon = Daemon::new(languages);
+        daemon.fetch.enabled = false;
+        let (first, _socket) = LspService::new(|client| daemon.session(client));
+        let (second, _socket) = LspService::new(|client| daemon.session(client));
+        let params = || InitializeParams {
+            initialization_options: Some(serde_json::json!({
+                "root": dir.display().to_string(),
+                "languages": ["rust"],
+            })),
+            ..Default::default()
+        };
+        let (first_result, second_result) = tokio::join!(
+            first.inner().initialize(params()),
+            second.inner().initialize(params())
+        );
+        assert!(first_result.is_ok() && second_result.is_ok());
+        assert_eq!(daemon.backends.lock().await.len(), 1);
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "started\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::document::{apply_change, position_to_offset};
+    use tower_lsp::lsp_types::*;
+
+    fn change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                Position::new(start.0, start.1),
+                Position::new(end.0, end.1),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_position_to_offset() {
+        let text = "modified   src/lib.rs\n+    let café = \"☕\";\n";
+        assert_eq!(position_to_offset(text, Position::new(0, 0)), 0);
+        assert_eq!(position_to_offset(text, Position::new(0, 8)), 8);
+        assert_eq!(position_to_offset(text, Position::new(1, 0)), 22);
+        // é is one UTF-16 unit but two bytes
+        assert_eq!(position_to_offset(text, Position::new(1, 13)), 22 + 14);
+        // Past the end of a line or of the text
+        assert_eq!(position_to_offset(text, Position::new(0, 100)), 21);
+        assert_eq!(position_to_offset(text, Position::new(5, 0)), text.len());
+
+        // 𝄞 is two UTF-16 units and four bytes
+        assert_eq!(position_to_offset("𝄞x", Position::new(0, 2)), 4);
+    }
+
+    #[test]
+    fn test_apply_change() {
+        let mut text = "@@ -1,2 +1,2 @@\n-old\n+new\n".to_string();
+        apply_change(&mut text, &change((2, 1), (2, 4), "newer"));
+        assert_eq!(text, "@@ -1,2 +1,2 @@\n-old\n+newer\n");
+
+        // Deleting a line, then inserting one
+        apply_change(&mut text, &change((1, 0), (2, 0), ""));
+        assert_eq!(text, "@@ -1,2 +1,2 @@\n+newer\n");
+        apply_change(&mut text, &change((1, 0), (1, 0), " context\n"));
+        assert_eq!(text, "@@ -1,2 +1,2 @@\n context\n+newer\n");
+
+        // Full sync replaces everything
+        let full = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "replaced".to_string(),
+        };
+        apply_change(&mut text, &full);
+        assert_eq!(text, "replaced");
+    }
+}
@@ -0,0 +1,101 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::config::{Config, FetchConfig};
+    use diff_lsp::vcs::{fetch, Vcs};
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn test_fetch_config() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config.fetch, FetchConfig::default());
+        assert!(config.fetch.enabled);
+
+        let config = Config::from_toml(
+            r#"
+[fetch]
+remote = "upstream"
+refspecs = ["main"]
+interval = 300
+
+[language.rust]
+command = "rust-analyzer"
+"#,
+        )
+        .unwrap();
+        assert!(config.fetch.enabled);
+        assert_eq!(config.fetch.remote, Some("upstream".to_string()));
+        assert_eq!(config.fetch.interval, Some(300));
+        assert!(config.languages.get("rust").is_some());
+
+        let config = Config::from_toml("[fetch]\nenabled = false").unwrap();
+        assert!(!config.fetch.enabled);
+        assert!(Config::from_toml("[fetch]\nremotes = [\"origin\"]").is_err());
+    }
+
+    #[test]
+    fn test_fetch_args() {
+        let none: Vec<String> = vec![];
+        assert_eq!(Vcs::Git.fetch_args(None, &none), vec!["fetch", "origin"]);
+        assert_eq!(
+            Vcs::Git.fetch_args(Some("upstream"), &["main".to_string()]),
+            vec!["fetch", "upstream", "main"]
+        );
+        assert_eq!(Vcs::Jujutsu.fetch_args(None, &none), vec!["git", "fetch"]);
+        assert_eq!(
+            Vcs::Jujutsu.fetch_args(Some("upstream"), &["main".to_string()]),
+            vec!["git", "fetch", "--branch", "main", "--remote", "upstream"]
+        );
+        assert_eq!(
+            Vcs::Mercurial.fetch_args(Some("default"), &["stable".to_string()]),
+            vec!["pull", "-r", "stable", "default"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_errors_are_returned() {
+        let root = std::env::temp_dir().join(format!("diff_lsp_fetch_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let root_str = root.to_str().unwrap();
+        // Not a repository, so nothing to do
+        assert!(fetch(root_str, &FetchConfig::default())
+            .await
+            .unwrap()
+            .is_none());
+
+        let status = Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(&root)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        // Without remotes there's nothing to fetch from
+        assert!(fetch(root_str, &FetchConfig::default())
+            .await
+            .unwrap()
+            .is_none());
+
+        let missing = root.join("missing");
+        let status = Command::new("git")
+            .args(["remote", "add", "origin", missing.to_str().unwrap()])
+            .current_dir(&root)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        // A remote which can't be fetched is an error to report rather than
+        // a panic
+        let error = fetch(root_str, &FetchConfig::default())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("git fetch origin failed"), "{}", error);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_without_root_is_an_error() {
+        // Rather than fetching wherever the daemon happens to be running
+        assert!(fetch("", &FetchConfig::default()).await.is_err());
+    }
+}
@@ -0,0 +1,104 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::config::LanguageRegistry;
+    use diff_lsp::parsers::github::GithubPullRequest;
+    use diff_lsp::parsers::registry::ParserRegistry;
+    use diff_lsp::parsers::utils::{
+        DiffHeader, InputLineNumber, LineType, Parsable, ParsedDiff, SourceLineNumber,
+    };
+    use diff_lsp::server::read_initialization_params_from_tempfile;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_detect_github_pr() {
+        let raw_pr = fs::read_to_string("tests/data/go_pr.github.json").unwrap();
+        let (entry, _) = ParserRegistry::default().detect(&raw_pr).unwrap();
+        assert_eq!(entry.format, "github-pr");
+
+        // Rendered PRs go back through the code-review parser
+        let (rendered, _) = GithubPullRequest::from_json(&raw_pr).unwrap().render();
+        let (entry, _) = ParserRegistry::default().detect(&rendered).unwrap();
+        assert_eq!(entry.format, "code-review");
+    }
+
+    #[test]
+    fn test_parse_github_pr() {
+        let raw_pr = fs::read_to_string("tests/data/go_pr.github.json").unwrap();
+        let diff = ParsedDiff::parse(&raw_pr).unwrap();
+        let (rendered, _) = GithubPullRequest::from_json(&raw_pr).unwrap().render();
+        for (i, line) in rendered.lines().enumerate() {
+            println!("{:>3} {}", i + 1, line);
+        }
+
+        assert_eq!(
+            diff.headers.get(&DiffHeader::Project),
+            Some(&"C-Hipple/gtdbot #9".to_string())
+        );
+        assert_eq!(
+            diff.headers.get(&DiffHeader::Head),
+            Some(&"9094cf7a1b2c3d4e5f60718293a4b5c6d7e8f901".to_string())
+        );
+        assert_eq!(
+            diff.filenames,
+            vec![
+                "config.go".to_string(),
+                "logo.png".to_string(),
+                "git_tools/release.go".to_string()
+            ]
+        );
+
+        let map = diff.map_diff_line_to_src(13).unwrap();
+        assert_eq!(map.file_name, "config.go".to_string());
+        assert_eq!(map.source_line_type, LineType::Unmodified);
+        assert_eq!(map.source_line, SourceLineNumber(17));
+
+        let map = diff.map_diff_line_to_src(16).unwrap();
+        assert_eq!(map.source_line_type, LineType::Added);
+        assert_eq!(map.source_line, SourceLineNumber(20));
+    }
+
+    #[test]
+    fn test_github_pr_threads() {
+        let raw_pr = fs::read_to_string("tests/data/go_pr.github.json").unwrap();
+        let diff = ParsedDiff::parse(&raw_pr).unwrap();
+        assert_eq!(diff.threads.len(), 3);
+
+        let thread = &diff.threads[0];
+        assert_eq!(thread.file_name, "config.go".to_string());
+        assert_eq!(thread.comment.author, "C-Hipple".to_string());
+        assert_eq!(
+            thread.comment.timestamp,
+            Some("2025-05-03 01:55:55".to_string())
+        );
+        assert_eq!(thread.replies.len(), 1);
+        assert_eq!(thread.replies[0].body, "agreed\nwill fix".to_string());
+        assert_eq!(thread.source_line, Some(SourceLineNumber(21)));
+        // Rendered under `type RawWorkflow struct {`
+        assert_eq!(thread.input_line, Some(InputLineNumber::new(17)));
+        let map = diff.map_diff_line_to_src(17).unwrap();
+        assert_eq!(map.source_line_text, " type RawWorkflow struct {");
+
+        // LEFT comments sit under the removed line
+        let left = &diff.threads[1];
+        let removed = diff
+            .map_diff_line_to_src(left.input_line.unwrap().0)
+            .unwrap();
+        assert_eq!(removed.source_line_type, LineType::Removed);
+
+        // Outdated comments aren't placed
+        assert!(diff.threads[2].input_line.is_none());
+        assert!(diff.threads[2].source_line.is_none());
+
+        // Comment boxes don't map to the source
+        assert!(diff.map_diff_line_to_src(18).is_none());
+    }
+
+    #[test]
+    fn test_github_pr_tempfile_languages() {
+        let path: PathBuf = "tests/data/go_pr.github.json".into();
+        let (_, _, file_types) =
+            read_initialization_params_from_tempfile(&path, &LanguageRegistry::default()).unwrap();
+        assert_eq!(file_types, vec!["go".to_string()]);
+    }
+}
@@ -0,0 +1,73 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::config::LanguageRegistry;
+    use diff_lsp::health::{find_executable, report, BackendStatus};
+    use diff_lsp::server::create_backends_map;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_find_executable() {
+        let sh = find_executable("sh").unwrap();
+        assert!(sh.is_absolute());
+        assert_eq!(find_executable(sh.to_str().unwrap()), Some(sh));
+        assert!(find_executable("diff-lsp-no-such-backend").is_none());
+        // Paths aren't looked up on $PATH
+        assert!(find_executable("./sh").is_none());
+        // Fixtures aren't executables
+        assert!(find_executable("tests/data/go_diff.magit_status").is_none());
+    }
+
+    #[test]
+    fn test_report() {
+        let statuses = vec![
+            BackendStatus {
+                language: "go".to_string(),
+                name: "gopls".to_string(),
+                command: "gopls".to_string(),
+                path: Some(PathBuf::from("/home/chris/go/bin/gopls")),
+                version: Some("golang.org/x/tools/gopls v0.15.3".to_string()),
+                error: None,
+            },
+            BackendStatus {
+                language: "zig".to_string(),
+                name: "zls".to_string(),
+                command: "zls".to_string(),
+                path: None,
+                version: None,
+                error: None,
+            },
+        ];
+        let report = report(&statuses);
+        let lines: Vec<&str> = report.lines().collect();
+        assert!(lines[0].starts_with("ok"));
+        assert!(lines[0].ends_with("/home/chris/go/bin/gopls (golang.org/x/tools/gopls v0.15.3)"));
+        assert!(lines[1].starts_with("missing"));
+        assert!(lines[1].ends_with("not found: zls"));
+        assert_eq!(lines[2], "1 of 2 backends available");
+    }
+
+    #[test]
+    fn test_missing_backends_are_skipped() {
+        let languages =
+            LanguageRegistry::from_toml("[language.go]\ncommand = \"diff-lsp-no-such-backend\"")
+                .unwrap();
+        let dir = std::env::current_dir().unwrap();
+        let backends =
+            create_backends_map(vec!["go".to_string()], dir.to_str().unwrap(), &languages).unwrap();
+        assert!(backends.is_empty());
+    }
+
+    #[test]
+    fn test_available_backends_start_without_missing_ones() {
+        let languages = LanguageRegistry::from_toml(concat!(
+            "[[language.go.backends]]\nname = \"missing\"\ncommand = \"diff-lsp-no-such-backend\"\n",
+            "[[language.go.backends]]\nname = \"cat\"\ncommand = \"cat\"\n",
+        ))
+        .unwrap();
+        let dir = std::env::current_dir().unwrap();
+        let backends =
+            create_backends_map(vec!["go".to_string()], dir.to_str().unwrap(), &languages).unwrap();
+        let started: Vec<&String> = backends.values().flatten().map(|(name, _)| name).collect();
+        assert_eq!(started, vec!["cat"]);
+    }
+}
@@ -0,0 +1,86 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::config::FetchConfig;
+    use diff_lsp::parsers::hg::{HgDiff, SaplingDiff};
+    use diff_lsp::parsers::registry::ParserRegistry;
+    use diff_lsp::parsers::utils::{DiffHeader, LineType, Parsable, SourceLineNumber};
+    use diff_lsp::vcs::{fetch, find_repo_root, Vcs};
+    use std::fs;
+
+    #[test]
+    fn test_parse_hg_diff() {
+        let raw_diff = fs::read_to_string("tests/data/go_diff.hg_diff").unwrap();
+        let (entry, _) = ParserRegistry::default().detect(&raw_diff).unwrap();
+        assert_eq!(entry.format, "hg-diff");
+
+        let diff = HgDiff::parse(&raw_diff).unwrap();
+        assert_eq!(diff.vcs, Vcs::Mercurial);
+        assert_eq!(
+            diff.filenames,
+            vec!["main.go".to_string(), "server/server.go".to_string()]
+        );
+        assert_eq!(
+            diff.headers.get(&DiffHeader::ParentChangeId),
+            Some(&"1a2b3c4d5e6f".to_string())
+        );
+
+        assert!(diff.map_diff_line_to_src(4).is_none());
+        let map = diff.map_diff_line_to_src(5).unwrap();
+        assert_eq!(map.source_line_type, LineType::Unmodified);
+        assert_eq!(map.source_line, SourceLineNumber(11));
+
+        let map = diff.map_diff_line_to_src(8).unwrap();
+        assert_eq!(map.source_line_type, LineType::Added);
+        assert_eq!(map.source_line, SourceLineNumber(14));
+
+        let map = diff.map_diff_line_to_src(17).unwrap();
+        assert_eq!(map.file_name, "server/server.go".to_string());
+        assert_eq!(map.source_line_type, LineType::Added);
+        assert_eq!(map.source_line, SourceLineNumber(2));
+    }
+
+    #[test]
+    fn test_parse_hg_export() {
+        let raw_diff = fs::read_to_string("tests/data/go_diff.hg_export").unwrap();
+        let (entry, _) = ParserRegistry::default().detect(&raw_diff).unwrap();
+        assert_eq!(entry.format, "hg-diff");
+
+        let diff = HgDiff::parse(&raw_diff).unwrap();
+        assert_eq!(diff.filenames, vec!["main.go".to_string()]);
+        assert_eq!(
+            diff.revision(),
+            Some(&"5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d3e4f".to_string())
+        );
+
+        let map = diff.map_diff_line_to_src(14).unwrap();
+        assert_eq!(map.source_line_type, LineType::Added);
+        assert_eq!(map.source_line, SourceLineNumber(13));
+    }
+
+    #[test]
+    fn test_parse_sapling_diff() {
+        let raw_diff = fs::read_to_string("tests/data/go_diff.hg_diff").unwrap();
+        let with_type = format!("Type: sl-diff\n\n{}", raw_diff);
+        let (entry, _) = ParserRegistry::default().detect(&with_type).unwrap();
+        assert_eq!(entry.format, "sl-diff");
+
+        let diff = SaplingDiff::parse(&with_type).unwrap();
+        assert_eq!(diff.vcs, Vcs::Sapling);
+        assert_eq!(diff.filenames.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_hg_repo_is_not_git_fetched() {
+        let root = std::env::temp_dir().join("diff_lsp_hg_root_test");
+        fs::create_dir_all(root.join(".hg")).unwrap();
+        assert_eq!(find_repo_root(&root), Some((root.clone(), Vcs::Mercurial)));
+        assert_eq!(Vcs::Mercurial.program(), "hg");
+        fs::remove_dir_all(&root).unwrap();
+
+        let not_a_repo = std::env::temp_dir().join("diff_lsp_no_repo_test");
+        fs::create_dir_all(&not_a_repo).unwrap();
+        let fetched = fetch(not_a_repo.to_str().unwrap(), &FetchConfig::default()).await;
+        assert!(fetched.unwrap().is_none());
+        fs::remove_dir_all(&not_a_repo).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{cat_session, open_diff};
+    use diff_lsp::config::{Config, HunkConfig};
+    use diff_lsp::hunks::{hunks, realign, HunkStatus};
+    use diff_lsp::parsers::utils::{Parsable, ParsedDiff, SourceLineNumber};
+    use diff_lsp::server::SharedBackends;
+    use std::fs;
+    use tower_lsp::lsp_types::*;
+
+    const DIFF: &str = "Buffer: PR #9
+Type: code-review
+
+modified   src/client.rs
+@@ -2,3 +2,4 @@
+ fn one() {}
++fn two() {}
+-fn three() {}
+ fn four() {}
+
+modified   src/lib.rs
+@@ -1,1 +1,2 @@
+ mod client;
++mod server;
+";
+
+    const CLIENT: &str = "fn zero() {}\nfn one() {}\nfn two() {}\nfn four() {}\n";
+
+    fn lines(text: &str) -> Vec<&str> {
+        text.lines().collect()
+    }
+
+    #[test]
+    fn test_hunk_config() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config.hunks, HunkConfig::default());
+        assert!(config.hunks.realign);
+
+        let config = Config::from_toml("[hunks]\nrealign = false\ndistance = 5").unwrap();
+        assert_eq!(
+            config.hunks,
+            HunkConfig {
+                realign: false,
+                distance: 5,
+                fuzz: 2,
+            }
+        );
+        assert!(Config::from_toml("[hunks]\nfuzz = -1").is_err());
+        assert!(Config::from_toml("[hunks]\noffset = 2").is_err());
+    }
+
+    #[test]
+    fn test_hunks() {
+        let diff = ParsedDiff::parse(DIFF).unwrap();
+        let hunks = hunks(&diff);
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(hunks[0].filename, "src/client.rs");
+        assert_eq!(hunks[0].lines.len(), 4);
+        assert_eq!(hunks[0].lines[1].1.content(), "fn two() {}");
+        assert_eq!(hunks[0].lines[2].1.content(), "fn three() {}");
+        assert_eq!(hunks[1].filename, "src/lib.rs");
+        assert!(hunks[0].last_line().0 < hunks[1].first_line().0);
+    }
+
+    #[test]
+    fn test_check_hunks() {
+        let diff = ParsedDiff::parse(DIFF).unwrap();
+        let hunk = &hunks(&diff)[0];
+        // Removed lines aren't in the file
+        assert_eq!(hunk.check(&lines(CLIENT), 50, 0), HunkStatus::Current);
+
+        let moved_down = format!("// License\n\n{}", CLIENT);
+        assert_eq!(hunk.check(&lines(&moved_down), 50, 0), HunkStatus::Moved(2));
+        assert_eq!(hunk.check(&lines(&moved_down), 1, 0), HunkStatus::Stale);
+        let moved_up = "fn one() {}\nfn two() {}\nfn four() {}\n";
+        assert_eq!(hunk.check(&lines(moved_up), 50, 0), HunkStatus::Moved(-1));
+        // Indentation doesn't matter
+        let indented = "fn zero() {}\n    fn one() {}\n  fn two() {}\nfn four() {}\n";
+        assert_eq!(hunk.check(&lines(indented), 50, 0), HunkStatus::Current);
+
+        let edited = "fn zero() {}\nfn one() {}\nfn deux() {}\nfn four() {}\n";
+        assert_eq!(hunk.check(&lines(edited), 50, 0), HunkStatus::Stale);
+        assert_eq!(hunk.check(&[], 50, 0), HunkStatus::Stale);
+    }
+
+    #[test]
+    fn test_relocate_with_fuzz() {
+        let diff = ParsedDiff::parse(DIFF).unwrap();
+        let hunk = &hunks(&diff)[0];
+        // The context either side of the added line was edited too
+        let edited = "// License\nfn zero() {}\nfn uno() {}\nfn two() {}\nfn cuatro() {}\n";
+        assert_eq!(hunk.relocate(&lines(edited), 50, 0), None);
+        assert_eq!(hunk.relocate(&lines(edited), 50, 1), Some(1));
+        assert_eq!(hunk.check(&lines(edited), 50, 1), HunkStatus::Moved(1));
+        // An exact match further away beats a fuzzy one nearby
+        let both = format!("{}\n\n{}", edited, CLIENT);
+        assert_eq!(hunk.relocate(&lines(&both), 50, 2), Some(7));
+        // The added lines always have to match
+        let rewritten = "fn zero() {}\nfn one() {}\nfn dos() {}\nfn four() {}\n";
+        assert_eq!(hunk.relocate(&lines(rewritten), 50, 2), None);
+    }
+
+    #[test]
+    fn test_realign() {
+        let mut diff = ParsedDiff::parse(DIFF).unwrap();
+        let hunk = hunks(&diff)[0].clone();
+        let other = hunks(&diff)[1].clone();
+        realign(&mut diff, &hunk, 2);
+        let source_map = diff.map_diff_line_to_src(hunk.first_line().0).unwrap();
+        assert_eq!(source_map.source_line, SourceLineNumber(4));
+        assert_eq!(source_map.offset, 2);
+        // The diff itself is left alone
+        assert_eq!(hunks(&diff)[0], hunk);
+        // Only the hunk moves
+        let source_map = diff.map_diff_line_to_src(other.first_line().0).unwrap();
+        assert_eq!(source_map.source_line, SourceLineNumber(1));
+        assert_eq!(source_map.offset, 0);
+
+        realign(&mut diff, &hunk, 0);
+        assert!(diff.offsets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_diffs_are_realigned() {
+        let root = std::env::temp_dir().join(format!("diff_lsp_hunks_{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join("src/client.rs"),
+            format!("// License\n\n{}", CLIENT),
+        )
+        .unwrap();
+        fs::write(root.join("src/lib.rs"), "mod client;\nmod server;\n").unwrap();
+        let open = |realign_hunks: bool| {
+            let root = root.clone();
+            async move {
+                let (service, _socket) =
+                    cat_session(&root, SharedBackends::default(), |diff_lsp| {
+                        diff_lsp.hunks.realign = realign_hunks;
+                    });
+                let uri = Url::parse("untitled:review").unwrap();
+                open_diff(service.inner(), &uri, DIFF).await;
+                let diff = service.inner().diff_map.lock().await[&uri].clone();
+                diff
+            }
+        };
+        // The first line of the client.rs hunk is on line 6 of the input
+        let source_line = |diff: &ParsedDiff| diff.map_diff_line_to_src(6).unwrap().source_line;
+
+        assert_eq!(source_line(&open(true).await), SourceLineNumber(4));
+        assert_eq!(source_line(&open(false).await), SourceLineNumber(2));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::config::LanguageRegistry;
+    use diff_lsp::parsers::jj::{JjDiff, JjStatus};
+    use diff_lsp::parsers::registry::ParserRegistry;
+    use diff_lsp::parsers::utils::{DiffHeader, LineType, Parsable, SourceLineNumber};
+    use diff_lsp::server::read_initialization_params_from_tempfile;
+    use diff_lsp::vcs::{find_repo_root, Vcs};
+    use std::fs;
+
+    #[test]
+    fn test_parse_jj_color_words_diff() {
+        let raw_diff = fs::read_to_string("tests/data/rust_diff.jj_diff").unwrap();
+        let diff = JjDiff::parse(&raw_diff).unwrap();
+        assert_eq!(diff.vcs, Vcs::Jujutsu);
+        assert_eq!(diff.revision(), Some(&"kxqpyzrw".to_string()));
+        assert_eq!(
+            diff.filenames,
+            vec!["src/lib.rs".to_string(), "src/registry.rs".to_string()]
+        );
+
+        let map = diff.map_diff_line_to_src(6).unwrap();
+        assert_eq!(map.source_line_type, LineType::Unmodified);
+        assert_eq!(map.source_line, SourceLineNumber(5));
+        assert_eq!(map.source_line_text, "pub mod client;");
+
+        let map = diff.map_diff_line_to_src(8).unwrap();
+        assert_eq!(map.source_line_type, LineType::Added);
+        assert_eq!(map.source_line, SourceLineNumber(7));
+
+        let map = diff.map_diff_line_to_src(10).unwrap();
+        assert_eq!(map.source_line_type, LineType::Removed);
+        assert_eq!(map.source_line, SourceLineNumber(9));
+
+        assert!(diff.map_diff_line_to_src(12).is_none());
+        let map = diff.map_diff_line_to_src(13).unwrap();
+        assert_eq!(map.file_name, "src/registry.rs".to_string());
+        assert_eq!(map.source_line, SourceLineNumber(1));
+    }
+
+    #[test]
+    fn test_parse_jj_git_diff() {
+        let raw_diff = fs::read_to_string("tests/data/rust_diff.jj_diff_git").unwrap();
+        let (entry, _) = ParserRegistry::default().detect(&raw_diff).unwrap();
+        assert_eq!(entry.format, "jj-diff");
+
+        let diff = JjDiff::parse(&raw_diff).unwrap();
+        assert_eq!(diff.filenames, vec!["src/lib.rs".to_string()]);
+        assert_eq!(
+            diff.headers.get(&DiffHeader::CommitId),
+            Some(&"6c8a1e4f".to_string())
+        );
+
+        let map = diff.map_diff_line_to_src(9).unwrap();
+        assert_eq!(map.source_line_type, LineType::Unmodified);
+        assert_eq!(map.source_line, SourceLineNumber(5));
+
+        let map = diff.map_diff_line_to_src(11).unwrap();
+        assert_eq!(map.source_line_type, LineType::Added);
+        assert_eq!(map.source_line, SourceLineNumber(7));
+
+        let map = diff.map_diff_line_to_src(13).unwrap();
+        assert_eq!(map.source_line_type, LineType::Removed);
+        assert_eq!(map.source_line, SourceLineNumber(9));
+    }
+
+    #[test]
+    fn test_parse_jj_status() {
+        let raw_status = fs::read_to_string("tests/data/rust_diff.jj_status").unwrap();
+        let (entry, _) = ParserRegistry::default().detect(&raw_status).unwrap();
+        assert_eq!(entry.format, "jj-status");
+
+        let status = JjStatus::parse(&raw_status).unwrap();
+        assert_eq!(
+            status.filenames,
+            vec![
+                "src/lib.rs".to_string(),
+                "src/registry.rs".to_string(),
+                "src/old_utils.rs".to_string()
+            ]
+        );
+        assert_eq!(status.revision(), Some(&"kxqpyzrw".to_string()));
+        assert_eq!(
+            status.headers.get(&DiffHeader::ParentChangeId),
+            Some(&"zzyqvnlm".to_string())
+        );
+
+        // Lines outside `Working copy changes:` aren't changed files
+        let status = JjStatus::parse(&format!("M Makefile\n{}A stray.rs\n", raw_status)).unwrap();
+        assert_eq!(status.filenames.len(), 3);
+        assert!(!status.filenames.contains(&"Makefile".to_string()));
+        assert!(!status.filenames.contains(&"stray.rs".to_string()));
+    }
+
+    #[test]
+    fn test_jj_status_initialization_params() {
+        let dir = std::env::temp_dir().join(format!("diff_lsp_jj_init_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("init_params");
+        // Only the changed files under `Working copy changes:` count, with
+        // renames taken by their new name
+        fs::write(
+            &path,
+            format!(
+                "Root: {}\nM Makefile\nWorking copy changes:\nA src/{{notes.txt => registry.rs}}\nWorking copy  (@) : kxqpyzrw 6c8a1e4f add a registry\n",
+                dir.display()
+            ),
+        )
+        .unwrap();
+        let (_, _, languages) =
+            read_initialization_params_from_tempfile(&path, &LanguageRegistry::default()).unwrap();
+        assert_eq!(languages, vec!["rust".to_string()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_jj_repo_root() {
+        let root = std::env::temp_dir().join("diff_lsp_jj_root_test");
+        let nested = root.join("src/nested");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(root.join(".jj")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+
+        assert_eq!(find_repo_root(&nested), Some((root.clone(), Vcs::Jujutsu)));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
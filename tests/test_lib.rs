@@ -24,42 +24,65 @@ impl log::Log for SimpleLogger {
 #[cfg(test)]
 mod tests {
     use super::SimpleLogger;
+    use diff_lsp::config::LanguageRegistry;
     use diff_lsp::parsers::code_review::CodeReviewDiff;
     use diff_lsp::parsers::magit::MagitDiff;
     use diff_lsp::parsers::utils::{DiffHeader, LineType, Parsable, ParsedDiff, SourceLineNumber};
-    use diff_lsp::{uri_from_relative_filename, SupportedFileType};
+    use diff_lsp::uri_from_relative_filename;
     use std::fs;
 
     #[allow(unused)]
     static LOGGER: SimpleLogger = SimpleLogger;
 
     #[test]
-    fn test_supported_file_type_from_filename() {
+    fn test_language_for_filename() {
+        let languages = LanguageRegistry::default();
+        let name = |filename: &str| languages.language_for(filename).map(|l| l.name.clone());
+        assert_eq!(name("Makefile"), Some("make".to_string()));
         assert_eq!(
-            SupportedFileType::from_filename("Makefile".to_string()),
-            None
+            name("docker/Dockerfile.dev"),
+            Some("dockerfile".to_string())
         );
         assert_eq!(
-            SupportedFileType::from_filename("hi.py".to_string()),
-            Some(SupportedFileType::Python)
-        );
-        assert_eq!(
-            SupportedFileType::from_filename("test.hi.py".to_string()),
-            Some(SupportedFileType::Python)
-        );
-        assert_eq!(
-            SupportedFileType::from_filename("test.hi.rs".to_string()),
-            Some(SupportedFileType::Rust)
-        );
-        assert_eq!(
-            SupportedFileType::from_filename("main.rs".to_string()),
-            Some(SupportedFileType::Rust)
+            name("third_party/BUILD.bazel"),
+            Some("starlark".to_string())
         );
+        assert_eq!(name("Gemfile"), Some("ruby".to_string()));
+        assert_eq!(name("src/parser.hpp"), Some("cpp".to_string()));
+        assert_eq!(name("App.kt"), Some("kotlin".to_string()));
+        assert_eq!(name("init.lua"), Some("lua".to_string()));
+        assert_eq!(name("build.zig"), Some("zig".to_string()));
+        assert_eq!(name("Main.hs"), Some("haskell".to_string()));
+        assert_eq!(name("index.mjs"), Some("javascript".to_string()));
+        assert_eq!(name("hi.py"), Some("python".to_string()));
+        assert_eq!(name("test.hi.py"), Some("python".to_string()));
+        assert_eq!(name("test.hi.rs"), Some("rust".to_string()));
+        assert_eq!(name("main.rs"), Some("rust".to_string()));
+        assert_eq!(name("main.go"), Some("go".to_string()));
+        assert_eq!(name("go"), None);
+        assert_eq!(name("bin/release"), None);
+    }
+
+    #[test]
+    fn test_language_for_shebang() {
+        let languages = LanguageRegistry::default();
+        let name = |line: &str| languages.language_for_shebang(line).map(|l| l.name.clone());
+        assert_eq!(name("#!/bin/bash"), Some("bash".to_string()));
+        assert_eq!(name("#!/usr/bin/env python3"), Some("python".to_string()));
+        assert_eq!(name("#!/usr/bin/python3.11 -u"), Some("python".to_string()));
         assert_eq!(
-            SupportedFileType::from_filename("main.go".to_string()),
-            Some(SupportedFileType::Go)
+            name("#!/usr/bin/env -S node --no-warnings"),
+            Some("javascript".to_string())
         );
-        assert_eq!(SupportedFileType::from_filename("go".to_string()), None);
+        assert_eq!(name("#!/usr/bin/env ruby"), Some("ruby".to_string()));
+        assert_eq!(name("#!/usr/bin/env perl"), None);
+        assert_eq!(name("# not a shebang"), None);
+
+        // Extensionless scripts are read from disk
+        let detect = |file: &str| languages.detect("tests/data", file).map(|l| l.name.clone());
+        assert_eq!(detect("release"), Some("bash".to_string()));
+        assert_eq!(detect("/release"), Some("bash".to_string()));
+        assert_eq!(detect("missing"), None);
     }
 
     #[test]
@@ -0,0 +1,237 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::config::LanguageRegistry;
+    use diff_lsp::merge::{merge_capabilities, merge_goto_responses, merge_locations};
+    use diff_lsp::server::{BackendMap, DiffLsp};
+    use std::fs;
+    use tower_lsp::lsp_types::*;
+    use tower_lsp::{LanguageServer, LspService};
+
+    fn location(path: &str, line: u32) -> Location {
+        Location {
+            uri: Url::parse(&format!("file:///src/{}", path)).unwrap(),
+            range: Range::new(Position::new(line, 4), Position::new(line, 10)),
+        }
+    }
+
+    #[test]
+    fn test_merge_locations() {
+        let merged = merge_locations(vec![
+            vec![location("lib.rs", 3), location("main.rs", 10)],
+            vec![location("main.rs", 10), location("client.rs", 7)],
+        ]);
+        assert_eq!(
+            merged,
+            vec![
+                location("lib.rs", 3),
+                location("main.rs", 10),
+                location("client.rs", 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_goto_responses() {
+        assert_eq!(merge_goto_responses(vec![]), None);
+
+        // One answer is passed on untouched
+        let scalar = GotoDefinitionResponse::Scalar(location("lib.rs", 3));
+        assert_eq!(
+            merge_goto_responses(vec![scalar.clone()]),
+            Some(scalar.clone())
+        );
+
+        let link = GotoDefinitionResponse::Link(vec![LocationLink {
+            origin_selection_range: None,
+            target_uri: location("lib.rs", 3).uri,
+            target_range: Range::new(Position::new(0, 0), Position::new(20, 0)),
+            target_selection_range: location("lib.rs", 3).range,
+        }]);
+        let array = GotoDefinitionResponse::Array(vec![location("main.rs", 10)]);
+        assert_eq!(
+            merge_goto_responses(vec![scalar, link, array]),
+            Some(GotoDefinitionResponse::Array(vec![
+                location("lib.rs", 3),
+                location("main.rs", 10)
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_merge_capabilities() {
+        let gopls = ServerCapabilities {
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            definition_provider: Some(OneOf::Left(true)),
+            ..Default::default()
+        };
+        let linter = ServerCapabilities {
+            references_provider: Some(OneOf::Left(true)),
+            hover_provider: Some(HoverProviderCapability::Simple(false)),
+            ..Default::default()
+        };
+        let merged = merge_capabilities(&[gopls, linter]);
+        assert_eq!(
+            merged.hover_provider,
+            Some(HoverProviderCapability::Simple(true))
+        );
+        assert_eq!(merged.definition_provider, Some(OneOf::Left(true)));
+        assert_eq!(merged.references_provider, Some(OneOf::Left(true)));
+        // Nothing provides type definitions so they aren't advertised
+        assert!(merged.type_definition_provider.is_none());
+    }
+
+    /// A session whose rust files are served by one fake backend per `modes`,
+    /// with a diff open at `untitled:review` adding line 2 of src/client.rs.
+    async fn serve(name: &str, modes: &[&str]) -> LspService<DiffLsp> {
+        let root = std::env::temp_dir().join(format!("diff_lsp_{}_{}", name, std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/client.rs"), "fn one() {}\nfn two() {}\n").unwrap();
+        let script = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/fake_backend.sh");
+        let config: String = modes
+            .iter()
+            .map(|mode| {
+                format!(
+                    "[[language.rust.backends]]\nname = \"{}\"\ncommand = \"sh\"\nargs = [\"{}\", \"{}\"]\n",
+                    mode, script, mode
+                )
+            })
+            .collect();
+        let languages = LanguageRegistry::from_toml(&config).unwrap();
+        let (service, _socket) = LspService::new(|client| {
+            DiffLsp::new(
+                client,
+                BackendMap::new(),
+                root.display().to_string(),
+                languages,
+            )
+        });
+        service
+            .inner()
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem {
+                    uri: Url::parse("untitled:review").unwrap(),
+                    language_id: "diff".to_string(),
+                    version: 1,
+                    text: "Type: code-review\n\nmodified   src/client.rs\n@@ -1,1 +1,2 @@\n fn one() {}\n+fn two() {}\n".to_string(),
+                },
+            })
+            .await;
+        service
+    }
+
+    fn position() -> TextDocumentPositionParams {
+        // The added line, as the diff buffer numbers it
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: Url::parse("untitled:review").unwrap(),
+            },
+            position: Position::new(6, 3),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_backends_are_skipped() {
+        let service = serve("skipped", &["fail", "answer"]).await;
+        let server = service.inner();
+        let hover = server
+            .hover(HoverParams {
+                text_document_position_params: position(),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            hover.contents,
+            HoverContents::Scalar(MarkedString::String("fake hover".to_string()))
+        );
+        let references = server
+            .references(ReferenceParams {
+                text_document_position: position(),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+                context: ReferenceContext {
+                    include_declaration: true,
+                },
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(references.len(), 1);
+
+        // Only when every backend fails is the request an error
+        let service = serve("all_failed", &["fail"]).await;
+        let result = service
+            .inner()
+            .hover(HoverParams {
+                text_document_position_params: position(),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            })
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_backend_diagnostics_are_moved_onto_the_diff() {
+        let service = serve("diagnostics", &["diagnose"]).await;
+        let server = service.inner();
+        let uri = Url::parse("untitled:review").unwrap();
+        // The backend publishes once it has the file open, which may be after
+        // the diff's diagnostics were first worked out
+        let mut diagnostics = vec![];
+        for _ in 0..50 {
+            server
+                .execute_command(ExecuteCommandParams {
+                    command: "check".to_string(),
+                    arguments: vec![],
+                    work_done_progress_params: WorkDoneProgressParams::default(),
+                })
+                .await
+                .unwrap();
+            diagnostics = server
+                .published
+                .lock()
+                .await
+                .get(&uri)
+                .cloned()
+                .unwrap_or_default();
+            if !diagnostics.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(diagnostics.len(), 1);
+        // `two` on line 2 of src/client.rs is on the added line, after its +
+        assert_eq!(
+            diagnostics[0].range,
+            Range::new(Position::new(5, 4), Position::new(5, 7))
+        );
+        assert_eq!(diagnostics[0].message, "fake error");
+        assert_eq!(diagnostics[0].source, Some("sh".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_merge_only_their_own_backends() {
+        // The fake backend can't go to definitions
+        let service = serve("own_backends", &["answer"]).await;
+        let backends = service.inner().backends.clone();
+        let (other, _socket) = LspService::new(|client| {
+            DiffLsp::with_shared_backends(
+                client,
+                backends,
+                std::env::temp_dir().display().to_string(),
+                LanguageRegistry::default(),
+            )
+        });
+        let result = other
+            .inner()
+            .initialize(InitializeParams::default())
+            .await
+            .unwrap();
+        // With no backends of its own, everything is advertised
+        assert_eq!(
+            result.capabilities.definition_provider,
+            Some(OneOf::Left(true))
+        );
+    }
+}
@@ -0,0 +1,109 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::parsers::code_review::CodeReviewDiff;
+    use diff_lsp::parsers::magit::MagitDiff;
+    use diff_lsp::parsers::registry::{ParserEntry, ParserRegistry};
+    use diff_lsp::parsers::utils::{parse_headers, DiffHeader, Parsable, ParsedDiff};
+    use std::fs;
+
+    #[test]
+    fn test_detect_by_type_header() {
+        let registry = ParserRegistry::default();
+
+        let magit = fs::read_to_string("tests/data/go_diff.magit_status").unwrap();
+        let (entry, confidence) = registry.detect(&magit).unwrap();
+        assert_eq!(entry.format, "magit-status");
+        assert_eq!(confidence, 100);
+
+        let code_review = fs::read_to_string("tests/data/go_diff.code_review_server").unwrap();
+        let (entry, confidence) = registry.detect(&code_review).unwrap();
+        assert_eq!(entry.format, "code-review");
+        assert_eq!(confidence, 100);
+
+        // Every alias of a format is detected as it
+        for diff_type in ["code-review", "my-code-review", "code-review-server"] {
+            let source =
+                code_review.replacen("Type: my-code-review", &format!("Type: {}", diff_type), 1);
+            let (entry, confidence) = registry.detect(&source).unwrap();
+            assert_eq!(entry.format, "code-review", "{}", diff_type);
+            assert_eq!(confidence, 100, "{}", diff_type);
+        }
+        let source = magit.replacen("Type: magit-status", "Type: magit", 1);
+        let (entry, confidence) = registry.detect(&source).unwrap();
+        assert_eq!(entry.format, "magit-status");
+        assert_eq!(confidence, 100);
+        assert!(registry.detect("Type: code-reviews\n").is_none());
+    }
+
+    #[test]
+    fn test_detect_by_custom_alias() {
+        let mut registry = ParserRegistry::new();
+        registry.register(ParserEntry::new::<CodeReviewDiff>("review", &["cr"]));
+        registry.register(ParserEntry::new::<MagitDiff>("magit-status", &[]));
+
+        let source = fs::read_to_string("tests/data/go_diff.code_review_server")
+            .unwrap()
+            .replacen("Type: my-code-review", "Type: cr", 1);
+        let (entry, confidence) = registry.detect(&source).unwrap();
+        assert_eq!(entry.format, "review");
+        assert_eq!(confidence, 100);
+    }
+
+    #[test]
+    fn test_type_string_in_body_is_not_detected() {
+        // A magit buffer whose diff happens to contain a code-review Type line
+        let source = "Type: magit-status\n\nUnstaged changes (1)\nmodified   README.md\n@@ -1,1 +1,2 @@\n README\n+Type: code-review\n";
+        let (entry, confidence) = ParserRegistry::default().detect(source).unwrap();
+        assert_eq!(entry.format, "magit-status");
+        assert_eq!(confidence, 100);
+
+        let diff = ParsedDiff::parse(source).unwrap();
+        assert_eq!(
+            diff.headers.get(&DiffHeader::Type),
+            Some(&"magit-status".to_string())
+        );
+    }
+
+    #[test]
+    fn test_detect_without_headers() {
+        let registry = ParserRegistry::default();
+        let source =
+            "Unstaged changes (1)\nmodified   main.go\n@@ -1,1 +1,2 @@\n package main\n+// hi\n";
+        let (entry, _) = registry.detect(source).unwrap();
+        assert_eq!(entry.format, "magit-status");
+
+        let source = "diff --git a/main.go b/main.go\n@@ -1,1 +1,2 @@\n package main\n+// hi\n";
+        let (entry, _) = registry.detect(source).unwrap();
+        assert_eq!(entry.format, "code-review");
+    }
+
+    #[test]
+    fn test_unknown_format_is_none() {
+        let registry = ParserRegistry::default();
+        assert!(registry.detect("just some text\n").is_none());
+        assert!(registry.parse("just some text\n", None).is_none());
+        assert!(registry
+            .parse("just some text\n", Some("not-a-format"))
+            .is_none());
+    }
+
+    #[test]
+    fn test_forced_format() {
+        let registry = ParserRegistry::default();
+        let source = "\nmodified   src/lib.rs\n@@ -1,1 +1,2 @@\n use std;\n+use log;\n";
+        let (entry, _) = registry.detect(source).unwrap();
+        assert_eq!(entry.format, "code-review");
+
+        let diff = registry.parse(source, Some("magit")).unwrap();
+        assert_eq!(diff.filenames, vec!["src/lib.rs".to_string()]);
+        assert_eq!(diff.lines_map.len(), 2);
+
+        let with_header = format!("Format: magit-status\n{}", source);
+        assert_eq!(
+            parse_headers(&with_header).get(&DiffHeader::Format),
+            Some(&"magit-status".to_string())
+        );
+        let diff = registry.parse(&with_header, None).unwrap();
+        assert_eq!(diff.filenames, vec!["src/lib.rs".to_string()]);
+    }
+}
@@ -0,0 +1,148 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::config::LanguageRegistry;
+    use diff_lsp::parsers::gerrit::GerritChange;
+    use diff_lsp::parsers::registry::ParserRegistry;
+    use diff_lsp::parsers::review::ReviewExport;
+    use diff_lsp::parsers::utils::{
+        DiffHeader, InputLineNumber, LineType, Parsable, ParsedDiff, SourceLineNumber,
+    };
+    use diff_lsp::server::read_initialization_params_from_tempfile;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_detect_review_exports() {
+        let registry = ParserRegistry::default();
+        let raw = fs::read_to_string("tests/data/go_change.gerrit.json").unwrap();
+        let (entry, _) = registry.detect(&raw).unwrap();
+        assert_eq!(entry.format, "gerrit");
+
+        let raw = fs::read_to_string("tests/data/go_mr.gitlab.json").unwrap();
+        let (entry, _) = registry.detect(&raw).unwrap();
+        assert_eq!(entry.format, "gitlab-mr");
+        assert!(registry.find("gitlab").is_some());
+    }
+
+    #[test]
+    fn test_gerrit_to_patch() {
+        let raw = fs::read_to_string("tests/data/go_change.gerrit.json").unwrap();
+        let change = GerritChange::from_json(&raw).unwrap();
+        let patch = GerritChange::to_patch(&change.files["config.go"]);
+        assert_eq!(
+            patch.lines().collect::<Vec<_>>(),
+            vec![
+                "@@ -17,5 +17,6 @@",
+                " \tWorkflows []workflows.Workflow",
+                " }",
+                " ",
+                "+// This struct implements all possible values a workflow can define.",
+                " type RawWorkflow struct {",
+                "-\tWorkflowType string",
+                "+\tWorkflowType        string",
+            ]
+        );
+
+        let patch = GerritChange::to_patch(&change.files["git_tools/release.go"]);
+        assert!(patch.starts_with("@@ -0,0 +1,3 @@\n"));
+    }
+
+    #[test]
+    fn test_parse_gerrit_change() {
+        let raw = fs::read_to_string("tests/data/go_change.gerrit.json").unwrap();
+        let diff = ParsedDiff::parse(&raw).unwrap();
+        let (rendered, _) = ReviewExport::from_json(&raw).unwrap().render();
+        for (i, line) in rendered.lines().enumerate() {
+            println!("{:>3} {}", i + 1, line);
+        }
+
+        assert_eq!(
+            diff.headers.get(&DiffHeader::Project),
+            Some(&"gtdbot #4242".to_string())
+        );
+        // The commit message isn't a file
+        assert_eq!(
+            diff.filenames,
+            vec!["config.go".to_string(), "git_tools/release.go".to_string()]
+        );
+
+        let map = diff.map_diff_line_to_src(12).unwrap();
+        assert_eq!(map.file_name, "config.go".to_string());
+        assert_eq!(map.source_line, SourceLineNumber(17));
+
+        assert_eq!(diff.threads.len(), 2);
+        let thread = &diff.threads[0];
+        assert_eq!(thread.comment.author, "chipple".to_string());
+        assert_eq!(
+            thread.comment.timestamp,
+            Some("2025-05-03 01:55:55".to_string())
+        );
+        // Replies to replies belong to the same thread
+        assert_eq!(thread.replies.len(), 2);
+        assert_eq!(thread.replies[1].body, "will fix".to_string());
+        assert_eq!(thread.source_line, Some(SourceLineNumber(21)));
+        assert_eq!(thread.input_line, Some(InputLineNumber::new(16)));
+
+        // PARENT comments sit under the removed line
+        let parent = &diff.threads[1];
+        let removed = diff
+            .map_diff_line_to_src(parent.input_line.unwrap().0)
+            .unwrap();
+        assert_eq!(removed.source_line_type, LineType::Removed);
+    }
+
+    #[test]
+    fn test_parse_gitlab_merge_request() {
+        let raw = fs::read_to_string("tests/data/go_mr.gitlab.json").unwrap();
+        let diff = ParsedDiff::parse(&raw).unwrap();
+        let (rendered, _) = ReviewExport::from_json(&raw).unwrap().render();
+        for (i, line) in rendered.lines().enumerate() {
+            println!("{:>3} {}", i + 1, line);
+        }
+
+        assert_eq!(
+            diff.headers.get(&DiffHeader::Project),
+            Some(&"chipple/gtdbot!12".to_string())
+        );
+        assert_eq!(
+            diff.headers.get(&DiffHeader::Head),
+            Some(&"9094cf7a1b2c3d4e5f60718293a4b5c6d7e8f901".to_string())
+        );
+        assert_eq!(
+            diff.filenames,
+            vec!["config.go".to_string(), "git_tools/release.go".to_string()]
+        );
+
+        // System notes aren't review threads
+        assert_eq!(diff.threads.len(), 2);
+        let thread = &diff.threads[0];
+        assert_eq!(thread.comment.id, Some("1126".to_string()));
+        assert_eq!(thread.replies.len(), 1);
+        assert_eq!(thread.source_line, Some(SourceLineNumber(21)));
+        let map = diff
+            .map_diff_line_to_src(thread.input_line.unwrap().0)
+            .unwrap();
+        assert_eq!(map.source_line_text, " type RawWorkflow struct {");
+
+        // Notes with only an old_line are on removed lines
+        let old = &diff.threads[1];
+        let removed = diff
+            .map_diff_line_to_src(old.input_line.unwrap().0)
+            .unwrap();
+        assert_eq!(removed.source_line_type, LineType::Removed);
+    }
+
+    #[test]
+    fn test_review_export_tempfile_languages() {
+        for path in [
+            "tests/data/go_change.gerrit.json",
+            "tests/data/go_mr.gitlab.json",
+        ] {
+            let path: PathBuf = path.into();
+            let (_, _, file_types) =
+                read_initialization_params_from_tempfile(&path, &LanguageRegistry::default())
+                    .unwrap();
+            assert_eq!(file_types, vec!["go".to_string()]);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+#[cfg(test)]
+mod tests {
+    use diff_lsp::config::LanguageRegistry;
+    use diff_lsp::parsers::code_review::CodeReviewDiff;
+    use diff_lsp::parsers::review::{ReviewComment, ReviewExport, ReviewThread};
+    use diff_lsp::parsers::utils::{InputLineNumber, Parsable, SourceLineNumber};
+    use diff_lsp::server::{BackendMap, DiffLsp};
+    use serde_json::{json, Value};
+    use std::fs;
+    use tower_lsp::lsp_types::*;
+    use tower_lsp::{LanguageServer, LspService};
+
+    #[test]
+    fn test_comment_boxes() {
+        let raw_diff = fs::read_to_string("tests/data/go_diff.code_review_server").unwrap();
+        let diff = CodeReviewDiff::parse(&raw_diff).unwrap();
+        assert_eq!(diff.threads.len(), 11);
+
+        let first = &diff.threads[0];
+        assert_eq!(first.file_name, "config.go");
+        assert_eq!(first.input_line, Some(InputLineNumber::new(80)));
+        assert_eq!(
+            first.comment,
+            ReviewComment {
+                id: Some("5".to_string()),
+                author: "local".to_string(),
+                timestamp: Some("0001-01-01 00:00:00".to_string()),
+                body: "on the comment // this struct".to_string(),
+            }
+        );
+        assert!(first.replies.is_empty());
+
+        let replied = &diff.threads[1];
+        assert_eq!(replied.comment.author, "C-Hipple");
+        assert_eq!(replied.comment.id, Some("2617542990".to_string()));
+        assert_eq!(replied.comment.body, "creating a local reply");
+        assert_eq!(
+            replied.replies,
+            vec![ReviewComment {
+                id: Some("6".to_string()),
+                author: "local".to_string(),
+                timestamp: None,
+                body: "reply to \"creating a local reply\"".to_string(),
+            }]
+        );
+        // The thread is on the removed line above the box
+        let (_, line) = &diff.lines_map[&replied.input_line.unwrap()];
+        assert_eq!(line.line, "- \tPrune        bool");
+        assert_eq!(replied.source_line, Some(line.source_line_number));
+
+        // Two boxes under one line are two threads on it
+        assert_eq!(diff.threads[5].input_line, diff.threads[6].input_line);
+        assert_eq!(diff.threads.last().unwrap().file_name, "main.go");
+    }
+
+    #[test]
+    fn test_old_review_comments() {
+        let raw_diff = fs::read_to_string("tests/data/full_go_diff.code_review").unwrap();
+        let diff = CodeReviewDiff::parse(&raw_diff).unwrap();
+        let thread = diff
+            .threads
+            .iter()
+            .find(|thread| thread.comment.body == "Early return?")
+            .unwrap();
+        assert_eq!(thread.comment.author, "C-Hipple");
+        assert_eq!(
+            thread.comment.timestamp,
+            Some("May 24, 2025, 21:36".to_string())
+        );
+        assert_eq!(thread.comment.id, None);
+        let (file_name, line) = &diff.lines_map[&thread.input_line.unwrap()];
+        assert_eq!(&thread.file_name, file_name);
+        assert_eq!(line.line, "+\t\treturn");
+        // Comment lines aren't mapped to the source
+        assert!(!diff.lines_map.contains_key(&InputLineNumber::new(249)));
+    }
+
+    #[test]
+    fn test_rendered_threads_round_trip() {
+        let export =
+            ReviewExport::from_json(&fs::read_to_string("tests/data/go_pr.github.json").unwrap())
+                .unwrap();
+        let (rendered, _) = export.render();
+        let diff = CodeReviewDiff::parse(&rendered).unwrap();
+        // Outdated threads aren't drawn, so only the export knows of them
+        let drawn: Vec<ReviewThread> = export
+            .parse()
+            .unwrap()
+            .threads
+            .into_iter()
+            .filter(|thread| thread.input_line.is_some())
+            .map(|mut thread| {
+                // Boxes don't show when replies were left
+                for reply in thread.replies.iter_mut() {
+                    reply.timestamp = None;
+                }
+                thread
+            })
+            .collect();
+        assert_eq!(drawn.len(), 2);
+        assert_eq!(diff.threads, drawn);
+        assert!(diff.threads.iter().all(|thread| thread
+            .source_line
+            .is_some_and(|line| line > SourceLineNumber(0))));
+    }
+
+    #[tokio::test]
+    async fn test_thread_commands() {
+        let languages =
+            LanguageRegistry::from_toml("[language.go]\ncommand = \"diff-lsp-no-such-backend\"")
+                .unwrap();
+        let (service, _socket) = LspService::new(|client| {
+            DiffLsp::new(client, BackendMap::new(), String::new(), languages)
+        });
+        let server = service.inner();
+        let uri = Url::parse("untitled:review").unwrap();
+        server
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem {
+                    uri: uri.clone(),
+                    language_id: "diff".to_string(),
+                    version: 1,
+                    text: fs::read_to_string("tests/data/go_diff.code_review_server").unwrap(),
+                },
+            })
+            .await;
+        let command = |command: &str, arguments: Vec<Value>| ExecuteCommandParams {
+            command: command.to_string(),
+            arguments,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        let threads = server
+            .execute_command(command("threads", vec![json!(uri)]))
+            .await
+            .unwrap()
+            .unwrap();
+        let threads = threads.as_array().unwrap();
+        assert_eq!(threads.len(), 11);
+        assert_eq!(threads[1]["diffLine"], json!(99));
+        assert_eq!(threads[1]["author"], json!("C-Hipple"));
+        assert_eq!(threads[1]["replies"][0]["id"], json!("6"));
+
+        // A realigned hunk moves its threads' file lines, not their diff lines
+        let line = threads[1]["line"].as_u64().unwrap();
+        server
+            .diff_map
+            .lock()
+            .await
+            .get_mut(&uri)
+            .unwrap()
+            .offsets
+            .insert(InputLineNumber(100), 3);
+        let moved = server
+            .execute_command(command("threads", vec![json!(uri)]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(moved[1]["line"], json!(line + 3));
+        assert_eq!(moved[1]["diffLine"], json!(99));
+
+        let jump = |name: &str, line: u32| {
+            server.execute_command(command(name, vec![json!(uri), json!(line)]))
+        };
+        let at_line = |line: u32| {
+            let position = Position::new(line, 0);
+            Some(json!(Location::new(
+                uri.clone(),
+                Range::new(position, position)
+            )))
+        };
+        assert_eq!(jump("next-thread", 0).await.unwrap(), at_line(79));
+        assert_eq!(jump("next-thread", 79).await.unwrap(), at_line(99));
+        assert_eq!(jump("previous-thread", 99).await.unwrap(), at_line(79));
+        assert_eq!(jump("previous-thread", 79).await.unwrap(), None);
+        assert_eq!(jump("next-thread", 10_000).await.unwrap(), None);
+
+        assert!(server
+            .execute_command(command("next-thread", vec![json!(uri)]))
+            .await
+            .is_err());
+        assert!(server
+            .execute_command(command("threads", vec![json!("untitled:other")]))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,202 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{cat_server, open_diff, open_text};
+    use diff_lsp::parsers::review::ReviewExport;
+    use diff_lsp::parsers::utils::Parsable;
+    use diff_lsp::parsers::utils::ParsedDiff;
+    use diff_lsp::vcs::{current_revision, is_checked_out, resolve_revision, show_file, Vcs};
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+    use tower_lsp::lsp_types::*;
+    use tower_lsp::LanguageServer;
+
+    fn source_revision(path: &str) -> Option<String> {
+        ParsedDiff::parse(&fs::read_to_string(path).unwrap())
+            .unwrap()
+            .source_revision()
+    }
+
+    #[test]
+    fn test_source_revision() {
+        // The branch a magit buffer shows
+        assert_eq!(
+            source_revision("tests/data/rust_diff.magit_status"),
+            Some("main".to_string())
+        );
+        // The head of Refs: main ... feature/include-release-callout
+        assert_eq!(
+            source_revision("tests/data/go_diff.code_review_server"),
+            Some("feature/include-release-callout".to_string())
+        );
+        assert_eq!(
+            source_revision("tests/data/rust_diff.jj_diff"),
+            Some("kxqpyzrw".to_string())
+        );
+        assert_eq!(
+            source_revision("tests/data/go_diff.hg_export"),
+            Some("5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d3e4f".to_string())
+        );
+
+        // The commit a pull request is at beats its branch
+        let pr = fs::read_to_string("tests/data/go_pr.github.json").unwrap();
+        let (rendered, _) = ReviewExport::from_json(&pr).unwrap().render();
+        let revision = ParsedDiff::parse(&rendered).unwrap().source_revision();
+        assert!(revision.is_some_and(|r| r.len() == 40), "{}", rendered);
+    }
+
+    fn git(root: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args([
+                "-c",
+                "user.name=diff-lsp",
+                "-c",
+                "user.email=diff-lsp@example.com",
+            ])
+            .args(args)
+            .current_dir(root)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    }
+
+    /// A repository with main checked out and a `review` branch which
+    /// changes src/client.rs.
+    fn review_repo(name: &str) -> std::path::PathBuf {
+        let root = std::env::temp_dir().join(format!("diff_lsp_{}_{}", name, std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        git(&root, &["init", "-q", "-b", "main"]);
+        fs::write(root.join("src/client.rs"), "fn main_version() {}\n").unwrap();
+        git(&root, &["add", "."]);
+        git(&root, &["commit", "-q", "-m", "main"]);
+        git(&root, &["checkout", "-q", "-b", "review"]);
+        fs::write(root.join("src/client.rs"), "fn review_version() {}\n").unwrap();
+        git(&root, &["commit", "-q", "-am", "review"]);
+        git(&root, &["checkout", "-q", "main"]);
+        root
+    }
+
+    #[test]
+    fn test_show_file_from_revision() {
+        let root = review_repo("show");
+        let root_str = root.to_str().unwrap();
+
+        let review = resolve_revision(root_str, Vcs::Git, "review", "origin").unwrap();
+        assert_eq!(review.len(), 40);
+        assert!(!is_checked_out(root_str, Vcs::Git, &review));
+        let main = resolve_revision(root_str, Vcs::Git, "main", "origin").unwrap();
+        assert!(is_checked_out(root_str, Vcs::Git, &main));
+        assert_eq!(current_revision(root_str, Vcs::Git), Some(main));
+        assert!(resolve_revision(root_str, Vcs::Git, "no-such-branch", "origin").is_none());
+
+        assert_eq!(
+            show_file(root_str, Vcs::Git, &review, "/src/client.rs").unwrap(),
+            "fn review_version() {}\n"
+        );
+        // Relative to the root, even in a subfolder of the repository
+        assert_eq!(
+            show_file(
+                root.join("src").to_str().unwrap(),
+                Vcs::Git,
+                &review,
+                "client.rs"
+            )
+            .unwrap(),
+            "fn review_version() {}\n"
+        );
+        assert!(show_file(root_str, Vcs::Git, &review, "src/missing.rs").is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backends_get_the_reviewed_revision() {
+        let root = review_repo("backends");
+        let (service, _socket) = cat_server(&root);
+        let server = service.inner();
+        let source_uri = Url::from_file_path(root.join("src/client.rs")).unwrap();
+        let diff = |head: &str| {
+            fs::read_to_string("tests/data/rust_diff.magit_status")
+                .unwrap()
+                .replace("Head:     main", &format!("Head:     {}", head))
+        };
+
+        // The branch isn't checked out, so its contents are sent
+        let review = Url::parse("untitled:review").unwrap();
+        open_diff(server, &review, &diff("review")).await;
+        assert_eq!(
+            open_text(server, &source_uri).await,
+            "fn review_version() {}\n"
+        );
+        server
+            .did_close(DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier { uri: review },
+            })
+            .await;
+
+        // What's checked out is read from the working copy, edits included
+        fs::write(root.join("src/client.rs"), "fn edited() {}\n").unwrap();
+        let status = Url::parse("untitled:status").unwrap();
+        open_diff(server, &status, &diff("main")).await;
+        assert_eq!(open_text(server, &source_uri).await, "fn edited() {}\n");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reviewed_revision_is_kept_until_refresh() {
+        let root = review_repo("kept");
+        let (service, _socket) = cat_server(&root);
+        let server = service.inner();
+        let uri = Url::parse("untitled:review").unwrap();
+        let source_uri = Url::from_file_path(root.join("src/client.rs")).unwrap();
+        let text = fs::read_to_string("tests/data/rust_diff.magit_status")
+            .unwrap()
+            .replace("Head:     main", "Head:     review");
+        open_diff(server, &uri, &text).await;
+        assert_eq!(
+            open_text(server, &source_uri).await,
+            "fn review_version() {}\n"
+        );
+
+        // The branch moves on, but edits to the diff don't look it up again
+        git(&root, &["checkout", "-q", "review"]);
+        fs::write(root.join("src/client.rs"), "fn newer_version() {}\n").unwrap();
+        git(&root, &["commit", "-q", "-am", "newer"]);
+        git(&root, &["checkout", "-q", "main"]);
+        server
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: 2,
+                },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: text.clone(),
+                }],
+            })
+            .await;
+        assert_eq!(
+            open_text(server, &source_uri).await,
+            "fn review_version() {}\n"
+        );
+
+        server
+            .execute_command(ExecuteCommandParams {
+                command: "refresh".to_string(),
+                arguments: vec![],
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            open_text(server, &source_uri).await,
+            "fn newer_version() {}\n"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
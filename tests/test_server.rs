@@ -1,14 +1,19 @@
+mod common;
+
 #[cfg(test)]
 mod tests {
 
+    use crate::common::{cat_server, cat_session, open_documents};
+    use diff_lsp::config::LanguageRegistry;
     use diff_lsp::parsers::magit::MagitDiff;
-    use diff_lsp::parsers::utils::{DiffHeader, Parsable};
+    use diff_lsp::parsers::utils::{DiffHeader, InputLineNumber, Parsable, ParsedDiff};
     use diff_lsp::server::create_backends_map;
     use diff_lsp::server::read_initialization_params_from_tempfile;
-    use diff_lsp::server::DiffLsp;
-    use diff_lsp::SupportedFileType;
+    use diff_lsp::server::{BackendMap, DiffLsp};
     use expanduser::expanduser;
     use log::info;
+    use serde_json::json;
+    use std::collections::HashMap;
     use std::fs;
     use std::path::PathBuf;
     use tower_lsp::lsp_types::*;
@@ -61,21 +66,278 @@ mod tests {
     #[test]
     fn test_get_initialization_params() {
         let path: PathBuf = "tests/data/full_go_diff.code_review".into();
-        let (cwd, worktree, file_types) = read_initialization_params_from_tempfile(&path).unwrap();
+        let (cwd, worktree, file_types) =
+            read_initialization_params_from_tempfile(&path, &LanguageRegistry::default()).unwrap();
         println!("types: {:?}", file_types);
         assert_eq!("/home/chris/gtdbot/".to_string(), cwd);
         assert!(worktree.is_none());
-        assert_eq!(file_types, vec![SupportedFileType::Go])
+        assert_eq!(file_types, vec!["go".to_string()])
     }
 
     #[test]
     fn test_get_initialization_params_with_worktree() {
         let path: PathBuf = "tests/data/worktree_test.init_params".into();
-        let (cwd, worktree, file_types) = read_initialization_params_from_tempfile(&path).unwrap();
+        let (cwd, worktree, file_types) =
+            read_initialization_params_from_tempfile(&path, &LanguageRegistry::default()).unwrap();
 
         assert!(cwd.ends_with("/tmp/test_root"));
         assert_eq!(Some("my_worktree".to_string()), worktree);
-        assert_eq!(file_types, vec![SupportedFileType::Rust]);
+        assert_eq!(file_types, vec!["rust".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_initialization_options() {
+        let root = std::env::temp_dir().join(format!("diff_lsp_init_{}", std::process::id()));
+        fs::create_dir_all(root.join("my_worktree")).unwrap();
+        // One initialize per service: nothing reads the socket, so a second
+        // log message from initialize would wait forever
+        let new_service = || {
+            LspService::new(|client| {
+                DiffLsp::new(
+                    client,
+                    BackendMap::new(),
+                    String::new(),
+                    LanguageRegistry::default(),
+                )
+            })
+        };
+
+        let (service, _socket) = new_service();
+        let mut params = get_init_params();
+        params.initialization_options = Some(json!({
+            "root": root.display().to_string(),
+            "worktree": "my_worktree",
+            "languages": ["go"],
+            "format": "magit-status",
+        }));
+        let result = service.inner().initialize(params).await.unwrap();
+        assert_eq!(
+            service.inner().root(),
+            root.join("my_worktree").display().to_string()
+        );
+        // No backends are running yet, so everything is offered
+        assert!(result.capabilities.hover_provider.is_some());
+
+        // Without options the editor's rootUri is used
+        let (service, _socket) = new_service();
+        let mut params = get_init_params();
+        params.root_uri = Some(Url::from_directory_path(&root).unwrap());
+        service.inner().initialize(params).await.unwrap();
+        assert_eq!(service.inner().root(), root.display().to_string());
+
+        let (service, _socket) = new_service();
+        let mut params = get_init_params();
+        params.initialization_options = Some(json!({"format": "unified-diff-ish"}));
+        assert!(service.inner().initialize(params).await.is_err());
+        let (service, _socket) = new_service();
+        let mut params = get_init_params();
+        params.initialization_options = Some(json!({"root": 42}));
+        assert!(service.inner().initialize(params).await.is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_did_change_reparses_in_memory() {
+        // No backend can start, so opening the diff only parses it
+        let languages =
+            LanguageRegistry::from_toml("[language.rust]\ncommand = \"diff-lsp-no-such-backend\"")
+                .unwrap();
+        let (service, _socket) = LspService::new(|client| {
+            DiffLsp::new(client, BackendMap::new(), String::new(), languages)
+        });
+        let server = service.inner();
+        // Not on disk: the editor's text is all there is
+        let uri = Url::parse("file:///nonexistent/review.diff-test").unwrap();
+        server.did_open(get_open_params_rust(uri.clone())).await;
+        let buffer = |diff_map: &HashMap<Url, ParsedDiff>| {
+            diff_map[&uri].headers.get(&DiffHeader::Buffer).cloned()
+        };
+        assert_eq!(
+            buffer(&*server.diff_map.lock().await),
+            Some("diff-lsp".to_string())
+        );
+        let lines_before = server.diff_map.lock().await[&uri].lines_map.clone();
+
+        // Rename the buffer, and add a line before the first hunk
+        let change = |start: Position, end: Position, text: &str| TextDocumentContentChangeEvent {
+            range: Some(Range::new(start, end)),
+            range_length: None,
+            text: text.to_string(),
+        };
+        server
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: 2,
+                },
+                content_changes: vec![
+                    change(Position::new(2, 8), Position::new(2, 16), "renamed"),
+                    change(Position::new(8, 0), Position::new(8, 0), "\n"),
+                ],
+            })
+            .await;
+        assert_eq!(
+            buffer(&*server.diff_map.lock().await),
+            Some("renamed".to_string())
+        );
+        // Every diff line moved down by one
+        let lines_after = server.diff_map.lock().await[&uri].lines_map.clone();
+        assert_eq!(lines_after.len(), lines_before.len());
+        for (line, diff_line) in lines_before.iter() {
+            assert_eq!(
+                lines_after.get(&InputLineNumber::new(line.0 + 1)),
+                Some(diff_line)
+            );
+        }
+
+        // A full sync replaces the whole diff
+        server
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: 3,
+                },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: fs::read_to_string("tests/data/go_diff.magit_status").unwrap(),
+                }],
+            })
+            .await;
+        assert_eq!(
+            buffer(&*server.diff_map.lock().await),
+            Some("lsp-example".to_string())
+        );
+
+        server
+            .did_close(DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+            })
+            .await;
+        assert!(server.diff_map.lock().await.is_empty());
+        assert!(server.documents.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_virtual_documents() {
+        let languages =
+            LanguageRegistry::from_toml("[language.rust]\ncommand = \"diff-lsp-no-such-backend\"")
+                .unwrap();
+        let (service, _socket) = LspService::new(|client| {
+            let mut server = DiffLsp::new(client, BackendMap::new(), String::new(), languages);
+            // Refreshing would otherwise fetch
+            server.fetch.enabled = false;
+            server
+        });
+        let server = service.inner();
+        let render = |uri: &str| ExecuteCommandParams {
+            command: "render".to_string(),
+            arguments: vec![json!(uri)],
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        for uri in ["untitled:Untitled-1", "diff-lsp:/reviews/42"] {
+            let uri = Url::parse(uri).unwrap();
+            server.did_open(get_open_params_rust(uri.clone())).await;
+            assert!(server.diff_map.lock().await.contains_key(&uri));
+            let rendered = server
+                .execute_command(render(uri.as_str()))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                rendered,
+                json!(fs::read_to_string("tests/data/rust_diff.magit_status").unwrap())
+            );
+        }
+
+        // Neither open nor on disk is an error rather than a panic
+        assert!(server
+            .execute_command(render("untitled:Untitled-2"))
+            .await
+            .is_err());
+        assert!(server
+            .execute_command(render("file:///nonexistent/review.diff-test"))
+            .await
+            .is_err());
+        server
+            .execute_command(ExecuteCommandParams {
+                command: "refresh".to_string(),
+                arguments: vec![],
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backend_documents_follow_disk() {
+        let root = std::env::temp_dir().join(format!("diff_lsp_sync_{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        let source = root.join("src/client.rs");
+        fs::write(&source, "fn one() {}").unwrap();
+        let (service, _socket) = cat_server(&root);
+        let server = service.inner();
+        // A second session, as a daemon would run, sharing the backend
+        let (other_service, _other_socket) = cat_session(&root, server.backends.clone(), |_| ());
+        let other_server = other_service.inner();
+        let open_documents = || open_documents(server);
+        let source_uri = Url::from_file_path(&source).unwrap();
+
+        let review = Url::parse("untitled:review").unwrap();
+        let status = Url::parse("untitled:status").unwrap();
+        server.did_open(get_open_params_rust(review.clone())).await;
+        assert_eq!(open_documents().await[&source_uri].version, 1);
+        server.did_open(get_open_params_rust(status.clone())).await;
+        other_server
+            .did_open(get_open_params_rust(review.clone()))
+            .await;
+        // Held three times but only opened once
+        let documents = open_documents().await;
+        assert_eq!(documents[&source_uri].version, 1);
+        assert_eq!(documents[&source_uri].holders.len(), 3);
+
+        // Edited and saved: edits to the diff alone don't read it again
+        fs::write(&source, "fn two() {}").unwrap();
+        server
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: review.clone(),
+                    version: 2,
+                },
+                content_changes: vec![],
+            })
+            .await;
+        assert_eq!(open_documents().await[&source_uri].version, 1);
+        // but a change to the file gets the backend the new text as the
+        // next version
+        server
+            .did_change_watched_files(DidChangeWatchedFilesParams {
+                changes: vec![FileEvent {
+                    uri: source_uri.clone(),
+                    typ: FileChangeType::CHANGED,
+                }],
+            })
+            .await;
+        let documents = open_documents().await;
+        assert_eq!(documents[&source_uri].version, 2);
+        assert_eq!(documents[&source_uri].text, "fn two() {}");
+
+        // Closing one diff leaves the file open for the others
+        let close = |uri: &Url| DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+        };
+        server.did_close(close(&review)).await;
+        server.did_close(close(&status)).await;
+        let documents = open_documents().await;
+        assert_eq!(documents[&source_uri].holders.len(), 1);
+
+        // Once no diff has the file it's closed
+        other_server.shutdown().await.unwrap();
+        assert!(open_documents().await.is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
     }
 
     #[allow(dead_code)]
@@ -103,11 +365,15 @@ mod tests {
             Some(&"diff-lsp".to_string())
         );
 
-        let backends = create_backends_map(vec![SupportedFileType::Rust], &root)
-            .expect("failed to create backends");
+        let backends = create_backends_map(
+            vec!["rust".to_string()],
+            &root,
+            &LanguageRegistry::default(),
+        )
+        .expect("failed to create backends");
         let (service, _socket) =
             // TODO: This no longer sets the diff to RAW_MAGIT_DIFF_RUST
-            LspService::new(|client| DiffLsp::new(client, backends, root));
+            LspService::new(|client| DiffLsp::new(client, backends, root, LanguageRegistry::default()));
 
         // TODO make relative and include in project.
         let url = Url::from_file_path("/Users/chrishipple/test7.diff-test").unwrap();
@@ -148,11 +414,12 @@ mod tests {
             diff.headers.get(&DiffHeader::Buffer),
             Some(&"lsp-example".to_string())
         );
-        let backends = create_backends_map(vec![SupportedFileType::Go], &root)
-            .expect("failed to create backends");
+        let backends =
+            create_backends_map(vec!["go".to_string()], &root, &LanguageRegistry::default())
+                .expect("failed to create backends");
         let (service, _socket) =
             // TODO: This no longer sets the diff to raw go diff
-            LspService::new(|client| DiffLsp::new(client, backends, root));
+            LspService::new(|client| DiffLsp::new(client, backends, root, LanguageRegistry::default()));
 
         // TODO make relative and include in project.
         let url = Url::from_file_path("/Users/chrishipple/lsp-example/main.go").unwrap();
@@ -0,0 +1,235 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{cat_session, open_diff};
+    use diff_lsp::config::{Config, WorktreeConfig};
+    use diff_lsp::server::{DiffRoots, SharedBackends};
+    use diff_lsp::worktree::{
+        close_review_worktree, ensure_review_worktree, prune_review_worktrees, review_worktrees,
+        worktree_path,
+    };
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use tower_lsp::lsp_types::*;
+    use tower_lsp::LanguageServer;
+
+    fn git(root: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args([
+                "-c",
+                "user.name=diff-lsp",
+                "-c",
+                "user.email=diff-lsp@example.com",
+            ])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?}", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    /// A repository with main checked out and a branch for each of
+    /// `branches`, which changes src/client.rs.
+    fn repo(name: &str, branches: &[&str]) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("diff_lsp_{}_{}", name, std::process::id()));
+        let root = base.join("repo");
+        fs::create_dir_all(root.join("src")).unwrap();
+        git(&root, &["init", "-q", "-b", "main"]);
+        fs::write(root.join("src/client.rs"), "fn main_version() {}\n").unwrap();
+        git(&root, &["add", "."]);
+        git(&root, &["commit", "-q", "-m", "main"]);
+        for branch in branches {
+            git(&root, &["checkout", "-q", "-b", branch]);
+            fs::write(root.join("src/client.rs"), format!("// {}\n", branch)).unwrap();
+            git(&root, &["commit", "-q", "-am", branch]);
+            git(&root, &["checkout", "-q", "main"]);
+        }
+        fs::canonicalize(root).unwrap()
+    }
+
+    fn config(repo: &Path, max: usize) -> WorktreeConfig {
+        WorktreeConfig {
+            enabled: true,
+            dir: Some(repo.with_file_name("worktrees").display().to_string()),
+            max,
+        }
+    }
+
+    #[test]
+    fn test_worktree_config() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config.worktrees, WorktreeConfig::default());
+        assert!(!config.worktrees.enabled);
+
+        let config =
+            Config::from_toml("[worktrees]\nenabled = true\ndir = \"/tmp/reviews\"\nmax = 5")
+                .unwrap();
+        assert!(config.worktrees.enabled);
+        assert_eq!(config.worktrees.dir, Some("/tmp/reviews".to_string()));
+        assert_eq!(config.worktrees.max, 5);
+        assert!(Config::from_toml("[worktrees]\nlimit = 5").is_err());
+    }
+
+    #[test]
+    fn test_worktree_path() {
+        let path = worktree_path(
+            Path::new("/cache/worktrees"),
+            Path::new("/home/chris/diff-lsp"),
+            "feature/add more_details",
+        );
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(path.starts_with("/cache/worktrees"));
+        assert!(name.starts_with("diff-lsp-"), "{}", name);
+        assert!(name.ends_with("-feature-add-more_details"), "{}", name);
+        assert_eq!(name.len(), "diff-lsp--feature-add-more_details".len() + 8);
+
+        // Repositories with the same name get their own worktrees
+        let other = worktree_path(
+            Path::new("/cache/worktrees"),
+            Path::new("/home/chris/forks/diff-lsp"),
+            "feature/add more_details",
+        );
+        assert_ne!(path, other);
+    }
+
+    #[test]
+    fn test_review_worktrees_are_capped() {
+        let repo = repo("worktrees", &["first", "second"]);
+        let config = config(&repo, 1);
+
+        let first_commit = git(&repo, &["rev-parse", "first"]);
+        let first = ensure_review_worktree(&repo, "first", &first_commit, &config).unwrap();
+        assert_eq!(
+            fs::read_to_string(first.join("src/client.rs")).unwrap(),
+            "// first\n"
+        );
+        // Made again, it's reused rather than added twice
+        assert_eq!(
+            ensure_review_worktree(&repo, "first", &first_commit, &config).unwrap(),
+            first
+        );
+        let second_commit = git(&repo, &["rev-parse", "second"]);
+        let second = ensure_review_worktree(&repo, "second", &second_commit, &config).unwrap();
+        assert_eq!(
+            review_worktrees(&repo, &config).unwrap(),
+            vec![second.clone(), first.clone()]
+        );
+
+        // Still being reviewed, so kept
+        let removed = prune_review_worktrees(&repo, &config, &[first.join("src")]).unwrap();
+        assert!(removed.is_empty());
+        let removed = prune_review_worktrees(&repo, &config, &[]).unwrap();
+        assert_eq!(removed, vec![first.clone()]);
+        assert!(!first.exists());
+        assert_eq!(review_worktrees(&repo, &config).unwrap(), vec![second]);
+        // The checkout itself is never touched
+        assert_eq!(
+            fs::read_to_string(repo.join("src/client.rs")).unwrap(),
+            "fn main_version() {}\n"
+        );
+
+        fs::remove_dir_all(repo.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_diffs_are_served_from_review_worktrees() {
+        let repo = repo("review_worktree", &["feature/x"]);
+        let (service, _socket) = cat_session(&repo, SharedBackends::default(), |server| {
+            server.worktrees = config(&repo, 3);
+        });
+        let server = service.inner();
+        let text = fs::read_to_string("tests/data/rust_diff.magit_status")
+            .unwrap()
+            .replace("Head:     main", "Head:     feature/x");
+        let uri = Url::parse("untitled:review").unwrap();
+        open_diff(server, &uri, &text).await;
+
+        let worktree = worktree_path(&repo.with_file_name("worktrees"), &repo, "feature/x");
+        assert!(worktree.join(".git").exists());
+        // Backends run in the worktree, and have its files open
+        let backends = server.backends.lock().await;
+        let (key, started) = backends.iter().next().unwrap();
+        assert_eq!(key.root, worktree.display().to_string());
+        let documents = started[0].1.lock().await.open_documents().clone();
+        let source_uri = Url::from_file_path(worktree.join("src/client.rs")).unwrap();
+        assert_eq!(documents[&source_uri].text, "// feature/x\n");
+        drop(backends);
+
+        fs::remove_dir_all(repo.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_closed_review_worktrees_are_removed() {
+        let repo = repo("closed_worktree", &["first"]);
+        let config = config(&repo, 3);
+        let commit = git(&repo, &["rev-parse", "first"]);
+        let first = ensure_review_worktree(&repo, "first", &commit, &config).unwrap();
+
+        // Another diff still reviews it
+        let removed =
+            close_review_worktree(&repo, &config, &first.join("src"), &[first.join("src")])
+                .unwrap();
+        assert!(removed.is_empty());
+        let removed = close_review_worktree(&repo, &config, &first.join("src"), &[]).unwrap();
+        assert_eq!(removed, vec![first.clone()]);
+        assert!(!first.exists());
+
+        fs::remove_dir_all(repo.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sessions_share_review_worktrees() {
+        let repo = repo("shared_worktrees", &["feature/x", "feature/y"]);
+        let backends: SharedBackends = Default::default();
+        let diff_roots: DiffRoots = Default::default();
+        let session = || {
+            let (service, _socket) = cat_session(&repo, backends.clone(), |server| {
+                // Only one worktree is kept once nothing is using it
+                server.worktrees = config(&repo, 1);
+                server.diff_roots = diff_roots.clone();
+            });
+            service
+        };
+        let uri = Url::parse("untitled:review").unwrap();
+        let diff = |head: &str| {
+            fs::read_to_string("tests/data/rust_diff.magit_status")
+                .unwrap()
+                .replace("Head:     main", &format!("Head:     {}", head))
+        };
+        let worktrees = repo.with_file_name("worktrees");
+        let (first, second) = (session(), session());
+        open_diff(first.inner(), &uri, &diff("feature/x")).await;
+        open_diff(second.inner(), &uri, &diff("feature/y")).await;
+        // The other session is still reviewing feature/x
+        assert!(worktree_path(&worktrees, &repo, "feature/x").exists());
+        assert!(worktree_path(&worktrees, &repo, "feature/y").exists());
+        assert_eq!(backends.lock().await.len(), 2);
+
+        // Closing the last diff using it removes it and its backends
+        first
+            .inner()
+            .did_close(DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+            })
+            .await;
+        assert!(!worktree_path(&worktrees, &repo, "feature/x").exists());
+        assert!(worktree_path(&worktrees, &repo, "feature/y").exists());
+        let roots: Vec<String> = backends
+            .lock()
+            .await
+            .keys()
+            .map(|key| key.root.clone())
+            .collect();
+        assert_eq!(
+            roots,
+            vec![worktree_path(&worktrees, &repo, "feature/y")
+                .display()
+                .to_string()]
+        );
+
+        fs::remove_dir_all(repo.parent().unwrap()).unwrap();
+    }
+}